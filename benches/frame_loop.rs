@@ -0,0 +1,70 @@
+// Cost of the per-frame work around rendering: clearing the color buffer, uploading
+// it to a texture and polling events. Compares allocating everything every frame,
+// as the renderer used to, with the persistent buffer, texture and event pump.
+// Run with `cargo bench --bench frame_loop`, it draws to an offscreen surface.
+use std::time::{Duration, Instant};
+
+use renderer_rs::display;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::surface::Surface;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 600;
+const FRAMES: u32 = 500;
+
+fn time_frames(mut frame: impl FnMut()) -> Duration {
+    // a few frames to warm up the caches and the driver
+    for _ in 0..10 {
+        frame();
+    }
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        frame();
+    }
+    start.elapsed() / FRAMES
+}
+
+fn main() {
+    let sdl_context = sdl2::init().unwrap();
+    let surface = Surface::new(WIDTH, HEIGHT, PixelFormatEnum::RGB24).unwrap();
+    let mut canvas = surface.into_canvas().unwrap();
+    let size = (WIDTH * HEIGHT * 3) as usize;
+    let background = Color::RGB(0, 0, 0);
+
+    let mut pixels = vec![0; size];
+    let allocating = time_frames(|| {
+        let mut event_pump = sdl_context.event_pump().unwrap();
+        for _event in event_pump.poll_iter() {}
+
+        pixels = vec![0; size];
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH, HEIGHT)
+            .unwrap();
+        texture.update(None, &pixels, (WIDTH * 3) as usize).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+    });
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut screen_texture = display::ScreenTexture::new();
+    let mut color_buffer = display::ColorBuffer::new(WIDTH, HEIGHT, display::PixelFormat::Rgb24);
+    let persistent = time_frames(|| {
+        for _event in event_pump.poll_iter() {}
+
+        display::clear_color_buffer(&mut color_buffer, background);
+        screen_texture.present(&mut canvas, &texture_creator, &color_buffer);
+    });
+
+    println!("{} frames of {}x{}", FRAMES, WIDTH, HEIGHT);
+    println!("allocating every frame: {:>8.3} ms", ms(allocating));
+    println!("persistent:             {:>8.3} ms", ms(persistent));
+    println!(
+        "saved per frame:        {:>8.3} ms",
+        ms(allocating.saturating_sub(persistent))
+    );
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
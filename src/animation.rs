@@ -0,0 +1,200 @@
+// Keyframe animation of node and camera transforms
+use serde::Deserialize;
+
+use crate::graph::SceneGraph;
+use crate::scene::Camera;
+use crate::vector::Vec3;
+
+// How values are picked between two keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Step,   // hold the value of the previous keyframe
+    Linear, // straight line between keyframes
+    Cubic,  // smooth curve through the keyframes (Catmull-Rom)
+}
+
+// Remaps the time between two keyframes, so motion can speed up or slow down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (2.0 - 2.0 * t).powi(2) / 2.0
+                }
+            }
+        }
+    }
+}
+
+// The value a track animates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    NodeTranslation(usize),
+    NodeRotation(usize), // radians
+    NodeScale(usize),
+    CameraPosition,
+    CameraTarget,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: f32, // seconds
+    pub value: Vec3,
+}
+
+pub struct Track {
+    pub channel: Channel,
+    pub keyframes: Vec<Keyframe>, // sorted by time
+    pub interpolation: Interpolation,
+    pub easing: Easing,
+}
+
+impl Track {
+    /// Track with its keyframes sorted by time
+    pub fn new(
+        channel: Channel,
+        mut keyframes: Vec<Keyframe>,
+        interpolation: Interpolation,
+        easing: Easing,
+    ) -> Track {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Track {
+            channel,
+            keyframes,
+            interpolation,
+            easing,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |key| key.time)
+    }
+
+    /// Value at `time`, holding the first and last keyframes outside of the track
+    pub fn sample(&self, time: f32) -> Option<Vec3> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        // keyframes i and i + 1 surround the time
+        let i = keys.partition_point(|key| key.time <= time) - 1;
+        let (k0, k1) = (keys[i], keys[i + 1]);
+        let t = self.easing.apply((time - k0.time) / (k1.time - k0.time));
+        Some(match self.interpolation {
+            Interpolation::Step => k0.value,
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * t,
+            Interpolation::Cubic => {
+                // the end keyframes are repeated to get tangents at the ends
+                let before = keys[i.saturating_sub(1)].value;
+                let after = keys[(i + 2).min(keys.len() - 1)].value;
+                catmull_rom(before, k0.value, k1.value, after, t)
+            }
+        })
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+pub struct Timeline {
+    pub tracks: Vec<Track>,
+    pub time: f32, // seconds
+    pub speed: f32,
+    pub playing: bool,
+    pub looping: bool,
+}
+
+impl Default for Timeline {
+    fn default() -> Timeline {
+        Timeline {
+            tracks: Vec::new(),
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: true,
+        }
+    }
+}
+
+impl Timeline {
+    /// Time of the last keyframe of all the tracks
+    pub fn duration(&self) -> f32 {
+        self.tracks.iter().map(Track::duration).fold(0.0, f32::max)
+    }
+
+    pub fn toggle_playing(&mut self) {
+        // playing again at the end of a timeline that doesn't loop starts over
+        if !self.playing && !self.looping && self.time >= self.duration() {
+            self.time = 0.0;
+        }
+        self.playing = !self.playing;
+    }
+
+    /// Jump to `time`, clamped to the timeline
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.duration());
+    }
+
+    /// Move the time forward by `seconds` when playing, wrapping around or stopping
+    /// at the end
+    pub fn advance(&mut self, seconds: f32) {
+        if !self.playing {
+            return;
+        }
+        let duration = self.duration();
+        self.time += seconds * self.speed;
+        if self.time >= duration {
+            if self.looping && duration > 0.0 {
+                self.time %= duration;
+            } else {
+                self.time = duration;
+                self.playing = false;
+            }
+        }
+    }
+
+    /// Set every animated value to its value at the current time
+    pub fn apply(&self, graph: &mut SceneGraph, camera: &mut Camera) {
+        for track in &self.tracks {
+            let value = match track.sample(self.time) {
+                Some(value) => value,
+                None => continue,
+            };
+            match track.channel {
+                Channel::NodeTranslation(node) => graph.nodes[node].translation = value,
+                Channel::NodeRotation(node) => graph.nodes[node].rotation = value,
+                Channel::NodeScale(node) => graph.nodes[node].scale = value,
+                Channel::CameraPosition => camera.position = value,
+                Channel::CameraTarget => camera.target = value,
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Bounding volumes
+use std::ops::Range;
+
+use crate::matrix::Matrix;
+use crate::mesh::Mesh;
+use crate::vector::Vec3;
+
+// Axis aligned bounding box
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+// Bounds of a contiguous range of faces, so parts of large meshes can be culled
+#[derive(Debug, Clone)]
+pub struct FaceChunk {
+    pub faces: Range<usize>,
+    pub sphere: BoundingSphere,
+}
+
+impl Aabb {
+    pub fn from_points(points: &[Vec3]) -> Option<Aabb> {
+        let first = *points.first()?;
+        let mut aabb = Aabb {
+            min: first,
+            max: first,
+        };
+        for p in points {
+            aabb.min = Vec3::new(
+                aabb.min.x.min(p.x),
+                aabb.min.y.min(p.y),
+                aabb.min.z.min(p.z),
+            );
+            aabb.max = Vec3::new(
+                aabb.max.x.max(p.x),
+                aabb.max.y.max(p.y),
+                aabb.max.z.max(p.z),
+            );
+        }
+        Some(aabb)
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+}
+
+impl BoundingSphere {
+    /// Sphere centered on the box around the points
+    pub fn from_points(points: &[Vec3]) -> Option<BoundingSphere> {
+        let center = Aabb::from_points(points)?.center();
+        let radius = points
+            .iter()
+            .map(|&p| (p - center).len())
+            .fold(0.0, f32::max);
+        Some(BoundingSphere { center, radius })
+    }
+
+    /// Sphere centered on the box around the centers of `spheres`, containing them all
+    pub fn enclosing(spheres: &[BoundingSphere]) -> Option<BoundingSphere> {
+        let centers: Vec<Vec3> = spheres.iter().map(|sphere| sphere.center).collect();
+        let center = Aabb::from_points(&centers)?.center();
+        let radius = spheres
+            .iter()
+            .map(|sphere| (sphere.center - center).len() + sphere.radius)
+            .fold(0.0, f32::max);
+        Some(BoundingSphere { center, radius })
+    }
+
+    /// The radius grows with the largest scale of the matrix
+    pub fn transform(&self, matrix: &Matrix) -> BoundingSphere {
+        let axis_scale =
+            |i: usize| Vec3::new(matrix.data[i][0], matrix.data[i][1], matrix.data[i][2]).len();
+        let scale = axis_scale(0).max(axis_scale(1)).max(axis_scale(2));
+        BoundingSphere {
+            center: matrix.transform(&self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl Mesh {
+    pub fn aabb(&self) -> Option<Aabb> {
+        Aabb::from_points(&self.vertices)
+    }
+
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(&self.vertices)
+    }
+
+    /// Split the faces into chunks of up to `faces_per_chunk` faces. Neighbouring faces
+    /// are usually stored close together, so the chunks stay reasonably compact.
+    pub fn face_chunks(&self, faces_per_chunk: usize) -> Vec<FaceChunk> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < self.faces.len() {
+            let end = (start + faces_per_chunk.max(1)).min(self.faces.len());
+            let points: Vec<Vec3> = self.faces[start..end]
+                .iter()
+                .flat_map(|face| [face.a, face.b, face.c])
+                .map(|index| self.vertices[index - 1])
+                .collect();
+            if let Some(sphere) = BoundingSphere::from_points(&points) {
+                chunks.push(FaceChunk {
+                    faces: start..end,
+                    sphere,
+                });
+            }
+            start = end;
+        }
+        chunks
+    }
+
+    /// Move the vertices so the center of the bounding box is the origin,
+    /// which is also the point the mesh rotates around
+    pub fn recenter(&mut self) {
+        if let Some(aabb) = self.aabb() {
+            let center = aabb.center();
+            for vertex in self.vertices.iter_mut() {
+                *vertex = *vertex - center;
+            }
+        }
+    }
+
+    /// Center the vertices and scale them so the largest side of the bounding box is 1
+    pub fn normalize_to_unit_box(&mut self) {
+        self.recenter();
+        if let Some(aabb) = self.aabb() {
+            let size = aabb.size();
+            let largest = size.x.max(size.y).max(size.z);
+            if largest > 0.0 {
+                for vertex in self.vertices.iter_mut() {
+                    *vertex = *vertex / largest;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Command line options
+use crate::display;
+use crate::mesh::Mesh;
+use crate::normals::NormalWeighting;
+use crate::validation;
+
+pub const USAGE: &str = "usage: renderer_rs [MODEL | SCENE | --primitive NAME] [--export OUTPUT [--bake]]
+
+  MODEL            .obj, .ply, .gltf or .glb file to open (default ./assets/f22.obj)
+  SCENE            .toml scene file with meshes, camera, lights and render settings
+  --primitive NAME use a built-in shape: cube, sphere, icosphere, cylinder, cone, torus, plane or capsule
+  --normalize      center the model and scale it to fit a unit box
+  --crease-angle DEGREES
+                   compute smooth normals, keeping edges sharper than DEGREES hard
+  --normal-weighting angle|area
+                   weight the faces around a vertex by their angle (default) or area
+  --instances COUNT
+                   show COUNT copies of the model on a grid
+  --fps LIMIT      frames per second to draw at most, or 'uncapped' (default 60)
+  --rgba           draw into an RGBA color buffer instead of RGB
+  --validate       print a report of problems in the model and exit
+  --repair         weld duplicate vertices, drop broken faces and unify the winding before use
+  --export OUTPUT  convert MODEL to an .obj, .stl or .ply file instead of opening a window
+  --bake           apply the model transform to the exported vertices";
+
+pub struct Options {
+    pub mesh_path: String,
+    pub primitive: Option<String>,
+    pub normalize: bool,
+    pub crease_angle: Option<f32>,
+    pub normal_weighting: NormalWeighting,
+    pub instances: usize,
+    pub max_fps: Option<u32>,
+    pub rgba: bool,
+    pub validate: bool,
+    pub repair: bool,
+    pub export_path: Option<String>,
+    pub bake_transform: bool,
+}
+
+impl Options {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            mesh_path: "./assets/f22.obj".to_string(),
+            primitive: None,
+            normalize: false,
+            crease_angle: None,
+            normal_weighting: NormalWeighting::Angle,
+            instances: 1,
+            max_fps: Some(display::FRAMES_PER_SECOND),
+            rgba: false,
+            validate: false,
+            repair: false,
+            export_path: None,
+            bake_transform: false,
+        };
+        let mut mesh_path = None;
+        // set by options that only apply to a single model
+        let mut mesh_processing = false;
+        let mut args = args;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--export" => {
+                    options.export_path = Some(args.next().ok_or("--export needs an output file")?)
+                }
+                "--primitive" => {
+                    options.primitive = Some(args.next().ok_or("--primitive needs a name")?)
+                }
+                "--crease-angle" => {
+                    let degrees = args
+                        .next()
+                        .ok_or("--crease-angle needs an angle in degrees")?;
+                    let degrees: f32 = degrees
+                        .parse()
+                        .map_err(|_| format!("invalid crease angle '{}'", degrees))?;
+                    options.crease_angle = Some(degrees.to_radians());
+                    mesh_processing = true;
+                }
+                "--normal-weighting" => {
+                    options.normal_weighting = match args.next().as_deref() {
+                        Some("angle") => NormalWeighting::Angle,
+                        Some("area") => NormalWeighting::Area,
+                        _ => return Err("--normal-weighting needs 'angle' or 'area'".to_string()),
+                    };
+                    mesh_processing = true;
+                }
+                "--instances" => {
+                    let count = args.next().ok_or("--instances needs a count")?;
+                    options.instances = count
+                        .parse()
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .ok_or(format!("invalid instance count '{}'", count))?;
+                }
+                "--fps" => {
+                    let limit = args.next().ok_or("--fps needs a limit")?;
+                    options.max_fps = match limit.as_str() {
+                        "uncapped" => None,
+                        _ => Some(
+                            limit
+                                .parse()
+                                .ok()
+                                .filter(|&fps| fps > 0)
+                                .ok_or(format!("invalid frame rate limit '{}'", limit))?,
+                        ),
+                    };
+                }
+                "--normalize" => {
+                    options.normalize = true;
+                    mesh_processing = true;
+                }
+                "--rgba" => options.rgba = true,
+                "--validate" => options.validate = true,
+                "--repair" => {
+                    options.repair = true;
+                    mesh_processing = true;
+                }
+                "--bake" => options.bake_transform = true,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ if arg.starts_with('-') => {
+                    return Err(format!("unknown option '{}'\n{}", arg, USAGE))
+                }
+                _ if mesh_path.is_none() => mesh_path = Some(arg),
+                _ => return Err(format!("unexpected argument '{}'\n{}", arg, USAGE)),
+            }
+        }
+
+        if options.bake_transform && options.export_path.is_none() {
+            return Err(format!("--bake only applies to --export\n{}", USAGE));
+        }
+        if mesh_path.is_some() && options.primitive.is_some() {
+            return Err(format!("give either a MODEL or --primitive\n{}", USAGE));
+        }
+        if let Some(mesh_path) = mesh_path {
+            options.mesh_path = mesh_path;
+        }
+        if options.is_scene()
+            && (options.validate || options.export_path.is_some() || options.instances > 1)
+        {
+            return Err(format!(
+                "--validate, --export and --instances don't apply to scene files\n{}",
+                USAGE
+            ));
+        }
+        if options.is_scene() && mesh_processing {
+            return Err(format!(
+                "--repair, --normalize, --crease-angle and --normal-weighting don't apply to \
+                 scene files\n{}",
+                USAGE
+            ));
+        }
+        Ok(options)
+    }
+
+    pub fn is_scene(&self) -> bool {
+        self.primitive.is_none() && self.mesh_path.to_ascii_lowercase().ends_with(".toml")
+    }
+
+    pub fn load_mesh(&self) -> Result<Mesh, String> {
+        let mut mesh = match &self.primitive {
+            Some(name) => Mesh::new_primitive(name)?,
+            None => Mesh::load(&self.mesh_path)?,
+        };
+        if self.repair {
+            eprint!("{}", mesh.repair(validation::WELD_EPSILON));
+        }
+        if self.normalize {
+            mesh.normalize_to_unit_box();
+        }
+        if let Some(crease_angle) = self.crease_angle {
+            mesh.compute_normals(self.normal_weighting, crease_angle);
+        }
+        Ok(mesh)
+    }
+}
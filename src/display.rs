@@ -1,31 +1,70 @@
 // This file contains the code for the display module
 extern crate sdl2;
-use sdl2::render::Canvas;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, RenderTarget, Texture, TextureCreator};
 use sdl2::video::{Window, WindowBuildError};
 
+use serde::Deserialize;
+
+use crate::render_state::{BlendMode, CullMode, RenderState, Winding};
 use crate::vector::Vec2;
 
 // Render Methods
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RenderMethod {
     Wireframe,             // default
     WireframeVertex,       // wireframe with vertex
     FillTriangle,          // fill triangle
     FillTriangleWireframe, // fill triangle with wireframe
+    Shaded,                // per-pixel shader programs with a depth buffer
 }
 
 // CULL METHODS
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CullMethod {
     None, // default
-    CullBackface,
+    #[serde(rename = "backface")]
+    CullBackface, // faces turned away from the camera in view space
+    #[serde(rename = "screen_back")]
+    CullScreenBack, // faces wound against the front face winding on the screen
+    #[serde(rename = "screen_front")]
+    CullScreenFront, // faces wound like the front face winding on the screen
+}
+
+impl CullMethod {
+    /// Render state culling the faces this method culls, front faces being wound as
+    /// `front_face`. Faces turned away in view space are the counter-clockwise ones on
+    /// the screen, whatever the front face winding.
+    pub fn render_state(&self, front_face: Winding) -> RenderState {
+        let (cull_mode, front_face) = match self {
+            CullMethod::None => (CullMode::None, front_face),
+            CullMethod::CullBackface => (CullMode::Back, Winding::Clockwise),
+            CullMethod::CullScreenBack => (CullMode::Back, front_face),
+            CullMethod::CullScreenFront => (CullMode::Front, front_face),
+        };
+        RenderState {
+            cull_mode,
+            front_face,
+            ..Default::default()
+        }
+    }
+}
+
+// How transparent instances are blended in shaded mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transparency {
+    Sorted,          // default, back to front by instance
+    WeightedBlended, // order independent, approximate
 }
 
 pub const WINDOW_WIDTH: u32 = 1020;
 pub const WINDOW_HEIGHT: u32 = 720;
 pub const FRAMES_PER_SECOND: u32 = 60;
 
-const WINDOW_TITLE: &str = "Renderer Learning";
+pub const WINDOW_TITLE: &str = "Renderer Learning";
 
 pub fn initialize_window(sdl_context: &sdl2::Sdl) -> Result<Window, WindowBuildError> {
     let video_subsystem = sdl_context.video().unwrap();
@@ -38,45 +77,195 @@ pub fn initialize_window(sdl_context: &sdl2::Sdl) -> Result<Window, WindowBuildE
     Ok(window)
 }
 
-pub fn clear_color_buffer(color_buffer: &mut Vec<u8>) {
-    *color_buffer = vec![0; (WINDOW_WIDTH * WINDOW_HEIGHT * 3) as usize];
+pub fn initialize_canvas(window: Window, vsync: bool) -> Result<Canvas<Window>, String> {
+    let mut canvas_builder = window.into_canvas().accelerated();
+    if vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    canvas_builder.build().map_err(|e| e.to_string())
+}
+
+// Layout of the pixels in the color buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelFormat {
+    Rgb24,  // default
+    Rgba32, // with the coverage of every pixel in alpha
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb24 => 3,
+            PixelFormat::Rgba32 => 4,
+        }
+    }
+
+    fn sdl_format(&self) -> PixelFormatEnum {
+        match self {
+            PixelFormat::Rgb24 => PixelFormatEnum::RGB24,
+            PixelFormat::Rgba32 => PixelFormatEnum::RGBA32,
+        }
+    }
+}
+
+// Pixels drawn in a frame, row by row
+pub struct ColorBuffer {
+    pub pixels: Vec<u8>,
+    pub format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ColorBuffer {
+    pub fn new(width: u32, height: u32, format: PixelFormat) -> ColorBuffer {
+        ColorBuffer {
+            pixels: vec![0; (width * height) as usize * format.bytes_per_pixel()],
+            format,
+            width,
+            height,
+        }
+    }
+
+    /// Bytes of the pixel at `x`, `y`, None outside of the buffer
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> Option<&mut [u8]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let size = self.format.bytes_per_pixel();
+        let index = (y * self.width + x) as usize * size;
+        Some(&mut self.pixels[index..index + size])
+    }
+
+    /// Bytes in a row of pixels
+    pub fn pitch(&self) -> usize {
+        self.width as usize * self.format.bytes_per_pixel()
+    }
+}
+
+/// Fill the color buffer with one color, in place
+pub fn clear_color_buffer(color_buffer: &mut ColorBuffer, color: sdl2::pixels::Color) {
+    let pixel = [color.r, color.g, color.b, color.a];
+    let pixel = &pixel[..color_buffer.format.bytes_per_pixel()];
+    if pixel.iter().all(|&channel| channel == pixel[0]) {
+        color_buffer.pixels.fill(pixel[0]);
+    } else {
+        for chunk in color_buffer.pixels.chunks_exact_mut(pixel.len()) {
+            chunk.copy_from_slice(pixel);
+        }
+    }
+}
+
+// Everything starts at the far plane
+pub fn clear_depth_buffer(depth_buffer: &mut [f32]) {
+    depth_buffer.fill(1.0);
+}
+
+// Streaming texture the color buffer is copied to, kept from one frame to the next.
+// It borrows the texture creator, so it is freed before the canvas goes away.
+pub struct ScreenTexture<'a> {
+    texture: Option<Texture<'a>>,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+}
+
+impl Default for ScreenTexture<'_> {
+    fn default() -> Self {
+        ScreenTexture::new()
+    }
 }
 
-pub fn render_color_buffer(canvas: &mut Canvas<Window>, color_buffer: &Vec<u8>) {
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(
-            sdl2::pixels::PixelFormatEnum::RGB24,
-            WINDOW_WIDTH,
-            WINDOW_HEIGHT,
-        )
-        .unwrap();
+impl<'a> ScreenTexture<'a> {
+    pub fn new() -> ScreenTexture<'a> {
+        ScreenTexture {
+            texture: None,
+            width: 0,
+            height: 0,
+            format: PixelFormat::Rgb24,
+        }
+    }
+
+    /// Copy the color buffer to the canvas. The texture is only created again when
+    /// the size or the pixel format changes, with `texture_creator` which must belong
+    /// to the canvas.
+    pub fn present<T: RenderTarget, C>(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        texture_creator: &'a TextureCreator<C>,
+        color_buffer: &ColorBuffer,
+    ) {
+        let (width, height, format) =
+            (color_buffer.width, color_buffer.height, color_buffer.format);
+        if self.texture.is_none()
+            || (self.width, self.height, self.format) != (width, height, format)
+        {
+            // the old texture is freed when it is replaced
+            let texture = texture_creator
+                .create_texture_streaming(format.sdl_format(), width, height)
+                .unwrap();
+            self.texture = Some(texture);
+            (self.width, self.height, self.format) = (width, height, format);
+        }
 
-    texture
-        .update(None, &color_buffer, (WINDOW_WIDTH * 3) as usize)
-        .unwrap();
-    canvas.copy(&texture, None, None).unwrap();
+        if let Some(texture) = self.texture.as_mut() {
+            texture
+                .update(None, &color_buffer.pixels, color_buffer.pitch())
+                .unwrap();
+            canvas.copy(texture, None, None).unwrap();
+        }
+    }
 }
 
-pub fn draw_pixel(color_buffer: &mut [u8], x: u32, y: u32, color: sdl2::pixels::Color) {
-    if x >= WINDOW_WIDTH || y >= color_buffer.len() as u32 / (WINDOW_WIDTH * 3) {
+pub fn draw_pixel(color_buffer: &mut ColorBuffer, x: u32, y: u32, color: sdl2::pixels::Color) {
+    if let Some(pixel) = color_buffer.pixel_mut(x, y) {
+        pixel[..3].copy_from_slice(&[color.r, color.g, color.b]);
+        if let Some(alpha) = pixel.get_mut(3) {
+            *alpha = color.a;
+        }
+    }
+}
+
+/// Blend `color` into the pixel at `x`, `y` with its alpha
+pub fn blend_pixel(
+    color_buffer: &mut ColorBuffer,
+    x: u32,
+    y: u32,
+    color: sdl2::pixels::Color,
+    blend: BlendMode,
+) {
+    if let Some(pixel) = color_buffer.pixel_mut(x, y) {
+        blend.apply(pixel, color, [true; 3]);
+    }
+}
+
+/// Blend `color` into the pixels of row `y` from `x0` to `x1`, both included
+pub fn draw_span(
+    color_buffer: &mut ColorBuffer,
+    x0: i32,
+    x1: i32,
+    y: i32,
+    color: sdl2::pixels::Color,
+    blend: BlendMode,
+) {
+    if y < 0 {
         return;
     }
-    let pixel_index = (y * WINDOW_WIDTH + x) as usize * 3;
-    color_buffer[pixel_index] = color.r;
-    color_buffer[pixel_index + 1] = color.g;
-    color_buffer[pixel_index + 2] = color.b;
+    // every pixel once, blending the same one twice would darken it
+    for x in x0.min(x1).max(0)..=x0.max(x1) {
+        blend_pixel(color_buffer, x as u32, y as u32, color, blend);
+    }
 }
 
 pub fn draw_rect(
-    color_buffer: &mut Vec<u8>,
+    color_buffer: &mut ColorBuffer,
     x: u32,
     y: u32,
     width: u32,
     height: u32,
     color: sdl2::pixels::Color,
 ) {
-    if x >= WINDOW_WIDTH || y >= WINDOW_HEIGHT {
+    if x >= color_buffer.width || y >= color_buffer.height {
         return;
     }
 
@@ -88,9 +277,9 @@ pub fn draw_rect(
 }
 
 #[allow(dead_code)]
-pub fn draw_grid(color_buffer: &mut Vec<u8>, size: usize) {
-    for y in (0..WINDOW_HEIGHT).step_by(size) {
-        for x in (0..WINDOW_WIDTH).step_by(size) {
+pub fn draw_grid(color_buffer: &mut ColorBuffer, size: usize) {
+    for y in (0..color_buffer.height).step_by(size) {
+        for x in (0..color_buffer.width).step_by(size) {
             draw_pixel(
                 color_buffer,
                 x,
@@ -102,7 +291,7 @@ pub fn draw_grid(color_buffer: &mut Vec<u8>, size: usize) {
 }
 
 pub fn draw_triangle(
-    color_buffer: &mut Vec<u8>,
+    color_buffer: &mut ColorBuffer,
     points: [Vec2; 3],
     color: sdl2::pixels::Color,
     allow_drawing_vertex: bool,
@@ -132,7 +321,7 @@ pub fn draw_triangle(
 }
 
 pub fn draw_line(
-    color_buffer: &mut Vec<u8>,
+    color_buffer: &mut ColorBuffer,
     mut x0: i32,
     mut y0: i32,
     x1: i32,
@@ -0,0 +1,267 @@
+// Writing meshes to OBJ, binary STL and binary PLY files
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::material::Image;
+use crate::mesh::Mesh;
+use crate::vector::Vec3;
+
+/// Save a mesh, picking the format from the file extension. With `bake_transform`
+/// the mesh rotation, scale and translation are applied to the written vertices.
+pub fn save(mesh: &Mesh, filename: &str, bake_transform: bool) -> Result<(), String> {
+    let mut baked;
+    let mesh = if bake_transform {
+        baked = mesh.clone();
+        baked.bake_transform();
+        &baked
+    } else {
+        mesh
+    };
+
+    let path = Path::new(filename);
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    let result = match extension.as_deref() {
+        Some("obj") => write_obj(mesh, path),
+        Some("stl") => create(path).and_then(|mut file| write_stl(mesh, &mut file)),
+        Some("ply") => create(path).and_then(|mut file| write_ply(mesh, &mut file)),
+        _ => return Err(format!("{}: unsupported export format", filename)),
+    };
+    result.map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn create(path: &Path) -> std::io::Result<BufWriter<File>> {
+    File::create(path).map(BufWriter::new)
+}
+
+fn face_normal(mesh: &Mesh, a: usize, b: usize, c: usize) -> Vec3 {
+    let vector_a = mesh.vertices[a - 1];
+    let vector_ab = mesh.vertices[b - 1] - vector_a;
+    let vector_ac = mesh.vertices[c - 1] - vector_a;
+    let normal = vector_ab.cross(vector_ac);
+    if normal.len() > 0.0 {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+// Writes the .obj and, when the mesh has a material, the .mtl next to it
+fn write_obj(mesh: &Mesh, path: &Path) -> std::io::Result<()> {
+    let mut file = create(path)?;
+    writeln!(file, "# exported by renderer_rs")?;
+
+    if let Some(material) = &mesh.material {
+        let mtl_path = path.with_extension("mtl");
+        write_mtl(mesh, &mtl_path)?;
+        let mtl_name = mtl_path.file_name().unwrap_or_default().to_string_lossy();
+        writeln!(file, "mtllib {}", mtl_name)?;
+        writeln!(file, "usemtl {}", material.name)?;
+    }
+
+    // attributes that don't cover every vertex are dropped
+    let has_uvs = mesh.uvs.len() == mesh.vertices.len();
+    let has_normals = mesh.normals.len() == mesh.vertices.len();
+    let has_vertex_colors = mesh.vertex_colors.len() == mesh.vertices.len();
+
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        match mesh.vertex_colors.get(i).filter(|_| has_vertex_colors) {
+            // vertex colors use the common "v x y z r g b" extension
+            Some(color) => writeln!(
+                file,
+                "v {:.6} {:.6} {:.6} {:.6} {:.6} {:.6}",
+                v.x,
+                v.y,
+                v.z,
+                color.r as f32 / 255.0,
+                color.g as f32 / 255.0,
+                color.b as f32 / 255.0
+            )?,
+            None => writeln!(file, "v {:.6} {:.6} {:.6}", v.x, v.y, v.z)?,
+        }
+    }
+    if has_uvs {
+        for uv in &mesh.uvs {
+            writeln!(file, "vt {:.6} {:.6}", uv.x, uv.y)?;
+        }
+    }
+    if has_normals {
+        for n in &mesh.normals {
+            writeln!(file, "vn {:.6} {:.6} {:.6}", n.x, n.y, n.z)?;
+        }
+    }
+
+    // attributes are per vertex, so every index in a face triplet is the same
+    let corner = |index: usize| match (has_uvs, has_normals) {
+        (true, true) => format!("{0}/{0}/{0}", index),
+        (true, false) => format!("{0}/{0}", index),
+        (false, true) => format!("{0}//{0}", index),
+        (false, false) => index.to_string(),
+    };
+    for face in &mesh.faces {
+        writeln!(
+            file,
+            "f {} {} {}",
+            corner(face.a),
+            corner(face.b),
+            corner(face.c)
+        )?;
+    }
+    file.flush()
+}
+
+fn write_mtl(mesh: &Mesh, path: &Path) -> std::io::Result<()> {
+    let material = match &mesh.material {
+        Some(material) => material,
+        None => return Ok(()),
+    };
+    let mut file = create(path)?;
+    let color = material.base_color;
+    writeln!(file, "newmtl {}", material.name)?;
+    writeln!(
+        file,
+        "Kd {:.6} {:.6} {:.6}",
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0
+    )?;
+    if color.a < 255 {
+        writeln!(file, "d {:.6}", color.a as f32 / 255.0)?;
+    }
+
+    match &material.base_color_texture {
+        Some(Image::Uri(uri)) => writeln!(file, "map_Kd {}", uri.display())?,
+        // embedded images are written out next to the material file
+        Some(Image::Embedded { mime_type, data }) => {
+            let extension = match mime_type.as_str() {
+                "image/jpeg" => "jpg",
+                _ => "png",
+            };
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let image_name = format!("{}_base_color.{}", stem, extension);
+            std::fs::write(path.with_file_name(&image_name), data)?;
+            writeln!(file, "map_Kd {}", image_name)?;
+        }
+        None => {}
+    }
+    file.flush()
+}
+
+fn write_stl(mesh: &Mesh, file: &mut impl Write) -> std::io::Result<()> {
+    let mut header = [0u8; 80];
+    let title = b"binary STL exported by renderer_rs";
+    header[..title.len()].copy_from_slice(title);
+    file.write_all(&header)?;
+    file.write_all(&(mesh.faces.len() as u32).to_le_bytes())?;
+
+    for face in &mesh.faces {
+        let normal = face_normal(mesh, face.a, face.b, face.c);
+        let points = [
+            normal,
+            mesh.vertices[face.a - 1],
+            mesh.vertices[face.b - 1],
+            mesh.vertices[face.c - 1],
+        ];
+        for point in points {
+            for value in [point.x, point.y, point.z] {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+        // attribute byte count, unused
+        file.write_all(&0u16.to_le_bytes())?;
+    }
+    file.flush()
+}
+
+fn write_ply(mesh: &Mesh, file: &mut impl Write) -> std::io::Result<()> {
+    // attributes that don't cover every vertex are dropped
+    let has_normals = mesh.normals.len() == mesh.vertices.len();
+    let has_uvs = mesh.uvs.len() == mesh.vertices.len();
+    let has_vertex_colors = mesh.vertex_colors.len() == mesh.vertices.len();
+
+    writeln!(file, "ply")?;
+    writeln!(file, "format binary_little_endian 1.0")?;
+    writeln!(file, "comment exported by renderer_rs")?;
+    writeln!(file, "element vertex {}", mesh.vertices.len())?;
+    for name in ["x", "y", "z"] {
+        writeln!(file, "property float {}", name)?;
+    }
+    if has_normals {
+        for name in ["nx", "ny", "nz"] {
+            writeln!(file, "property float {}", name)?;
+        }
+    }
+    if has_uvs {
+        for name in ["u", "v"] {
+            writeln!(file, "property float {}", name)?;
+        }
+    }
+    if has_vertex_colors {
+        for name in ["red", "green", "blue", "alpha"] {
+            writeln!(file, "property uchar {}", name)?;
+        }
+    }
+    writeln!(file, "element face {}", mesh.faces.len())?;
+    writeln!(file, "property list uchar int vertex_indices")?;
+    // without vertex colors the face colors are kept per face
+    if !has_vertex_colors {
+        for name in ["red", "green", "blue", "alpha"] {
+            writeln!(file, "property uchar {}", name)?;
+        }
+    }
+    writeln!(file, "end_header")?;
+
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        let mut values = vec![v.x, v.y, v.z];
+        if has_normals {
+            let n = mesh.normals[i];
+            values.extend([n.x, n.y, n.z]);
+        }
+        if has_uvs {
+            values.extend([mesh.uvs[i].x, mesh.uvs[i].y]);
+        }
+        for value in values {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        if has_vertex_colors {
+            let color = mesh.vertex_colors[i];
+            file.write_all(&[color.r, color.g, color.b, color.a])?;
+        }
+    }
+
+    for face in &mesh.faces {
+        file.write_all(&[3])?;
+        // ply indices are 0-based
+        for index in [face.a, face.b, face.c] {
+            file.write_all(&(index as i32 - 1).to_le_bytes())?;
+        }
+        if !has_vertex_colors {
+            file.write_all(&[face.color.r, face.color.g, face.color.b, face.color.a])?;
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_attributes_are_dropped() {
+        let mut mesh = Mesh::new_cube();
+        mesh.normals = vec![Vec3::new(0.0, 1.0, 0.0)];
+        mesh.uvs.clear();
+        mesh.vertex_colors.clear();
+
+        let mut bytes = Vec::new();
+        write_ply(&mesh, &mut bytes).unwrap();
+        let loaded = Mesh::from_ply_bytes(&bytes).unwrap();
+        assert_eq!(loaded.vertices.len(), mesh.vertices.len());
+        assert_eq!(loaded.faces.len(), mesh.faces.len());
+        assert!(loaded.normals.is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+// View frustum culling
+use crate::bounds::BoundingSphere;
+use crate::matrix::Matrix;
+use crate::vector::Vec3;
+
+// Points p with normal.dot(p) + distance >= 0 are on the inner side
+#[derive(Debug, Clone, Copy)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_coefficients(a: f32, b: f32, c: f32, d: f32) -> Plane {
+        let length = Vec3::new(a, b, c).len();
+        Plane {
+            normal: Vec3::new(a / length, b / length, c / length),
+            distance: d / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    pub planes: [Plane; 6], // left, right, bottom, top, near, far
+}
+
+impl Frustum {
+    /// Extract the planes from a projection matrix, or a view matrix multiplied by it.
+    /// The planes are in the space the matrix transforms from, so culling against
+    /// `view.multiply_matrix(&projection)` works with world space bounds.
+    pub fn from_matrix(matrix: &Matrix) -> Frustum {
+        // clip space component j of a point is its dot product with column j
+        let column = |j: usize| [0, 1, 2, 3].map(|i| matrix.data[i][j]);
+        let (x, y, z, w) = (column(0), column(1), column(2), column(3));
+        let plane = |sign: f32, c: [f32; 4], base: [f32; 4]| {
+            Plane::from_coefficients(
+                base[0] + sign * c[0],
+                base[1] + sign * c[1],
+                base[2] + sign * c[2],
+                base[3] + sign * c[3],
+            )
+        };
+        let zero = [0.0; 4];
+        Frustum {
+            planes: [
+                plane(1.0, x, w),    // -w <= x
+                plane(-1.0, x, w),   // x <= w
+                plane(1.0, y, w),    // -w <= y
+                plane(-1.0, y, w),   // y <= w
+                plane(1.0, z, zero), // 0 <= z
+                plane(-1.0, z, w),   // z <= w
+            ],
+        }
+    }
+
+    /// False only when the sphere is entirely outside one of the planes
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+}
@@ -0,0 +1,135 @@
+// Markers drawn over the frame to show where the lights are and where they point: an
+// arrow for directional lights, a star for point lights and a star with its cone for
+// spot lights
+use sdl2::pixels::Color;
+
+use crate::display::{self, ColorBuffer};
+use crate::light::{Light, LightKind};
+use crate::pipeline::VertexStage;
+use crate::vector::{Vec2, Vec3};
+
+// Pixels of the arrow heads and the arms of the stars
+const HEAD_SIZE: f32 = 10.0;
+const STAR_SIZE: f32 = 6.0;
+
+// Projects world space lines to the screen, dropping the parts behind the camera
+pub struct Gizmos<'a> {
+    pub color_buffer: &'a mut ColorBuffer,
+    pub stage: VertexStage, // with the view matrix as model view
+    pub near: f32,
+}
+
+impl Gizmos<'_> {
+    /// Draw the marker of `light`. Directional lights have no position, their arrow
+    /// ends at `anchor`; `size` is the length of the arrow and of the cone in world
+    /// units
+    pub fn light(&mut self, light: &Light, anchor: Vec3, size: f32, color: Color) {
+        let direction = light.direction.normalize();
+        match light.kind {
+            LightKind::Directional => self.arrow(anchor - direction * size, anchor, color),
+            LightKind::Point => self.star(light.position, color),
+            LightKind::Spot => {
+                self.star(light.position, color);
+                self.arrow(light.position, light.position + direction * size, color);
+                // the outer cone, as four lines around the direction
+                let side = if direction.y.abs() < 0.99 {
+                    Vec3::new(0.0, 1.0, 0.0)
+                } else {
+                    Vec3::new(1.0, 0.0, 0.0)
+                };
+                let u = direction.cross(side).normalize();
+                let v = direction.cross(u);
+                let radius = light.outer_cone.min(89f32.to_radians()).tan() * size;
+                let base = light.position + direction * size;
+                for offset in [u, -u, v, -v] {
+                    self.line(light.position, base + offset * radius, color);
+                }
+            }
+        }
+    }
+
+    fn arrow(&mut self, from: Vec3, to: Vec3, color: Color) {
+        let Some((a, b)) = self.project_line(from, to) else {
+            return;
+        };
+        draw_line(self.color_buffer, a, b, color);
+        let along = b - a;
+        if along.len() <= f32::EPSILON {
+            return;
+        }
+        // two strokes at 30 degrees from the shaft
+        let back = along * (-HEAD_SIZE / along.len());
+        for angle in [30f32.to_radians(), -30f32.to_radians()] {
+            let (sin, cos) = angle.sin_cos();
+            let stroke = Vec2::new(back.x * cos - back.y * sin, back.x * sin + back.y * cos);
+            draw_line(self.color_buffer, b, b + stroke, color);
+        }
+    }
+
+    fn star(&mut self, center: Vec3, color: Color) {
+        let vertex = self.stage.transform(center);
+        if vertex.view.z < self.near {
+            return;
+        }
+        let center = vertex.screen;
+        let diagonal = STAR_SIZE * std::f32::consts::FRAC_1_SQRT_2;
+        for arm in [
+            Vec2::new(STAR_SIZE, 0.0),
+            Vec2::new(0.0, STAR_SIZE),
+            Vec2::new(diagonal, diagonal),
+            Vec2::new(diagonal, -diagonal),
+        ] {
+            draw_line(self.color_buffer, center - arm, center + arm, color);
+        }
+    }
+
+    fn line(&mut self, from: Vec3, to: Vec3, color: Color) {
+        if let Some((a, b)) = self.project_line(from, to) {
+            draw_line(self.color_buffer, a, b, color);
+        }
+    }
+
+    // Screen ends of the part of the line in front of the near plane
+    fn project_line(&self, from: Vec3, to: Vec3) -> Option<(Vec2, Vec2)> {
+        let depth = |point: Vec3| self.stage.transform(point).view.z;
+        let (from_depth, to_depth) = (depth(from), depth(to));
+        if from_depth < self.near && to_depth < self.near {
+            return None;
+        }
+        // move the end behind the camera up to the near plane
+        let clip = |behind: Vec3, behind_depth: f32, front: Vec3, front_depth: f32| {
+            let t = (self.near - behind_depth) / (front_depth - behind_depth);
+            behind + (front - behind) * t
+        };
+        let (from, to) = if from_depth < self.near {
+            (clip(from, from_depth, to, to_depth), to)
+        } else if to_depth < self.near {
+            (from, clip(to, to_depth, from, from_depth))
+        } else {
+            (from, to)
+        };
+        Some((
+            self.stage.transform(from).screen,
+            self.stage.transform(to).screen,
+        ))
+    }
+}
+
+// Lines far off the screen are skipped, drawing them would walk every pixel on the way
+fn draw_line(color_buffer: &mut ColorBuffer, a: Vec2, b: Vec2, color: Color) {
+    let limit = 4.0 * color_buffer.width.max(color_buffer.height) as f32;
+    if [a.x, a.y, b.x, b.y]
+        .iter()
+        .any(|c| !c.is_finite() || c.abs() > limit)
+    {
+        return;
+    }
+    display::draw_line(
+        color_buffer,
+        a.x as i32,
+        a.y as i32,
+        b.x as i32,
+        b.y as i32,
+        color,
+    );
+}
@@ -0,0 +1,1073 @@
+// glTF 2.0 (.gltf and .glb) loading
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use sdl2::pixels::Color;
+use serde::Deserialize;
+
+use crate::material::{Image, Material, Texture};
+use crate::matrix::Matrix;
+use crate::mesh::{self, Mesh};
+use crate::triangle::Face;
+use crate::vector::{Vec2, Vec3, Vec4};
+
+pub struct Node {
+    pub name: String,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub translation: Vec3,
+    pub rotation: [f32; 4], // unit quaternion (x, y, z, w)
+    pub scale: Vec3,
+}
+
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+    // one mesh per glTF mesh, with all its primitives merged, and the material of the
+    // first primitive that has one
+    pub meshes: Vec<Mesh>,
+}
+
+impl Node {
+    pub fn local_matrix(&self) -> Matrix {
+        let [x, y, z, w] = self.rotation;
+        Matrix::new()
+            .scale(self.scale.x, self.scale.y, self.scale.z)
+            .multiply_matrix(&Matrix::rotate_quaternion(x, y, z, w))
+            .multiply_matrix(&Matrix::new().translate(
+                self.translation.x,
+                self.translation.y,
+                self.translation.z,
+            ))
+    }
+
+    /// Rotation as the x, y, z angles used by `Mesh::rotation`
+    pub fn euler_rotation(&self) -> Vec3 {
+        let [x, y, z, w] = self.rotation;
+        let m = Matrix::rotate_quaternion(x, y, z, w);
+        Vec3::new(
+            m.data[1][2].atan2(m.data[2][2]),
+            (-m.data[0][2]).clamp(-1.0, 1.0).asin(),
+            m.data[0][1].atan2(m.data[0][0]),
+        )
+    }
+}
+
+impl Scene {
+    /// Flatten the scene into a single mesh. A single root node keeps its
+    /// transform in the mesh transform fields, everything else is baked into the vertices.
+    /// A mesh has one material, the first one found: the faces of the other primitives
+    /// keep their base colors, but not their textures or opacity.
+    pub fn into_mesh(self) -> Mesh {
+        let mut merged = Mesh::from_geometry(Vec::new(), Vec::new());
+        let single_root = if self.roots.len() == 1 {
+            Some(self.roots[0])
+        } else {
+            None
+        };
+
+        for &root in &self.roots {
+            let world = if single_root == Some(root) {
+                Matrix::identity()
+            } else {
+                self.nodes[root].local_matrix()
+            };
+            self.append_node(root, &world, &mut merged);
+        }
+
+        if let Some(root) = single_root {
+            let node = &self.nodes[root];
+            merged.translation = node.translation;
+            merged.scale = node.scale;
+            merged.rotation = node.euler_rotation();
+        }
+        merged
+    }
+
+    fn append_node(&self, index: usize, world: &Matrix, merged: &mut Mesh) {
+        let node = &self.nodes[index];
+        if let Some(mesh) = node.mesh {
+            append_mesh(&self.meshes[mesh], world, merged);
+        }
+        for &child in &node.children {
+            let child_world = self.nodes[child].local_matrix().multiply_matrix(world);
+            self.append_node(child, &child_world, merged);
+        }
+    }
+}
+
+fn append_mesh(mesh: &Mesh, world: &Matrix, merged: &mut Mesh) {
+    let offset = merged.vertices.len();
+    let count = mesh.vertices.len();
+
+    // the inverse transpose keeps the normals perpendicular under non-uniform scale,
+    // and w = 0 keeps the translation out of them
+    let mut normal_matrix = world
+        .inverse()
+        .map_or(*world, |inverse| inverse.transpose());
+    let normals: Vec<Vec3> = mesh
+        .normals
+        .iter()
+        .map(|n| {
+            Vec3::from_vec4(normal_matrix.multiply(&mut Vec4::new(n.x, n.y, n.z, 0.0))).normalize()
+        })
+        .collect();
+    extend_attribute(
+        &mut merged.normals,
+        offset,
+        &normals,
+        count,
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    extend_attribute(
+        &mut merged.uvs,
+        offset,
+        &mesh.uvs,
+        count,
+        Vec2::new(0.0, 0.0),
+    );
+    extend_attribute(
+        &mut merged.vertex_colors,
+        offset,
+        &mesh.vertex_colors,
+        count,
+        Color::RGBA(255, 255, 255, 255),
+    );
+
+    merged
+        .vertices
+        .extend(mesh.vertices.iter().map(|v| world.transform(v)));
+    merged.faces.extend(mesh.faces.iter().map(|face| {
+        Face::new(
+            face.a + offset,
+            face.b + offset,
+            face.c + offset,
+            face.color,
+        )
+    }));
+    // only the first material is kept, the face colors already hold the base colors
+    if merged.material.is_none() {
+        merged.material = mesh.material.clone();
+    }
+}
+
+// keep optional attributes aligned with the vertices when meshes are merged
+fn extend_attribute<T: Copy>(
+    merged: &mut Vec<T>,
+    offset: usize,
+    values: &[T],
+    count: usize,
+    default: T,
+) {
+    if values.is_empty() && merged.is_empty() {
+        return;
+    }
+    merged.resize(offset, default);
+    if values.is_empty() {
+        merged.resize(offset + count, default);
+    } else {
+        merged.extend_from_slice(values);
+    }
+}
+
+pub fn load(filename: &str) -> Result<Scene, String> {
+    let bytes = std::fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let base = Path::new(filename).parent().unwrap_or(Path::new("."));
+    load_from_bytes(&bytes, base).map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn load_from_bytes(bytes: &[u8], base: &Path) -> Result<Scene, String> {
+    let (json, bin) = if bytes.starts_with(b"glTF") {
+        read_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+    let document: Document = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+
+    let mut buffers: Vec<Vec<u8>> = Vec::new();
+    for (index, buffer) in document.buffers.iter().enumerate() {
+        let data = match &buffer.uri {
+            Some(uri) if uri.starts_with("data:") => decode_data_uri(uri)?,
+            Some(uri) => std::fs::read(base.join(uri)).map_err(|e| format!("{}: {}", uri, e))?,
+            None if index == 0 => bin
+                .ok_or("buffer 0 has no uri and there is no GLB binary chunk")?
+                .to_vec(),
+            None => return Err(format!("buffer {} has no uri", index)),
+        };
+        if data.len() < buffer.byte_length {
+            return Err(format!("buffer {} is shorter than its byteLength", index));
+        }
+        buffers.push(data);
+    }
+
+    let mut meshes: Vec<Mesh> = Vec::new();
+    for doc_mesh in &document.meshes {
+        let mut mesh = Mesh::from_geometry(Vec::new(), Vec::new());
+        for primitive in &doc_mesh.primitives {
+            if let Some(part) = read_primitive(&document, &buffers, base, primitive)? {
+                append_mesh(&part, &Matrix::identity(), &mut mesh);
+            }
+        }
+        meshes.push(mesh);
+    }
+
+    let mut nodes: Vec<Node> = Vec::new();
+    for (index, doc_node) in document.nodes.iter().enumerate() {
+        if let Some(mesh) = doc_node.mesh.filter(|&mesh| mesh >= meshes.len()) {
+            return Err(format!("node {} references missing mesh {}", index, mesh));
+        }
+        if let Some(child) = doc_node
+            .children
+            .iter()
+            .find(|&&child| child >= document.nodes.len())
+        {
+            return Err(format!("node {} references missing child {}", index, child));
+        }
+
+        let mut node = Node {
+            name: doc_node
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("node{}", index)),
+            children: doc_node.children.clone(),
+            mesh: doc_node.mesh,
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        };
+        if let Some(matrix) = doc_node.matrix {
+            decompose_matrix(&matrix, &mut node);
+        }
+        if let Some([x, y, z]) = doc_node.translation {
+            node.translation = Vec3::new(x, y, z);
+        }
+        if let Some(rotation) = doc_node.rotation {
+            node.rotation = rotation;
+        }
+        if let Some([x, y, z]) = doc_node.scale {
+            node.scale = Vec3::new(x, y, z);
+        }
+        nodes.push(node);
+    }
+
+    // the loaders walk the hierarchy recursively, so it has to be a forest
+    let mut parents: Vec<Option<usize>> = vec![None; nodes.len()];
+    for (index, node) in nodes.iter().enumerate() {
+        for &child in &node.children {
+            if parents[child].replace(index).is_some() {
+                return Err(format!("node {} has more than one parent", child));
+            }
+        }
+    }
+    for start in 0..nodes.len() {
+        let mut node = start;
+        for _ in 0..=nodes.len() {
+            match parents[node] {
+                Some(parent) if parent == start => {
+                    return Err(format!("node {} is its own ancestor", start))
+                }
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+
+    let roots = match document.scene.or(if document.scenes.is_empty() {
+        None
+    } else {
+        Some(0)
+    }) {
+        Some(scene) => document
+            .scenes
+            .get(scene)
+            .ok_or(format!("missing scene {}", scene))?
+            .nodes
+            .clone(),
+        // without scenes every node that is nobody's child is a root
+        None => (0..nodes.len())
+            .filter(|&index| !nodes.iter().any(|node| node.children.contains(&index)))
+            .collect(),
+    };
+    if let Some(root) = roots.iter().find(|&&root| root >= nodes.len()) {
+        return Err(format!("scene references missing node {}", root));
+    }
+
+    Ok(Scene {
+        nodes,
+        roots,
+        meshes,
+    })
+}
+
+fn read_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    const CHUNK_JSON: u32 = 0x4E4F534A;
+    const CHUNK_BIN: u32 = 0x004E4942;
+
+    let read_u32 = |offset: usize| -> Result<u32, String> {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| "truncated GLB file".to_string())
+    };
+
+    if read_u32(4)? != 2 {
+        return Err("only GLB version 2 is supported".to_string());
+    }
+    let length = (read_u32(8)? as usize).min(bytes.len());
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(offset)? as usize;
+        let chunk_type = read_u32(offset + 4)?;
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or("truncated GLB chunk")?;
+        match chunk_type {
+            CHUNK_JSON if json.is_none() => json = Some(chunk),
+            CHUNK_BIN if bin.is_none() => bin = Some(chunk),
+            _ => {}
+        }
+        offset += 8 + chunk_length;
+    }
+    Ok((json.ok_or("GLB file has no JSON chunk")?, bin))
+}
+
+fn read_primitive(
+    document: &Document,
+    buffers: &[Vec<u8>],
+    base: &Path,
+    primitive: &DocPrimitive,
+) -> Result<Option<Mesh>, String> {
+    // only triangle modes can be rasterized
+    let mode = primitive.mode.unwrap_or(4);
+    if !(4..=6).contains(&mode) {
+        return Ok(None);
+    }
+
+    let position = *primitive
+        .attributes
+        .get("POSITION")
+        .ok_or("primitive has no POSITION attribute")?;
+    let (_, positions) = read_attribute(document, buffers, position, "POSITION", &[3])?;
+    let vertices: Vec<Vec3> = positions
+        .chunks(3)
+        .map(|p| Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32))
+        .collect();
+
+    // every attribute has a value for each vertex
+    let count = vertices.len();
+    let check_count = |name: &str, accessor: usize, len: usize| {
+        if len == count {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} accessor {} has {} elements, POSITION has {}",
+                name, accessor, len, count
+            ))
+        }
+    };
+
+    let mut mesh = Mesh::from_geometry(vertices, Vec::new());
+    if let Some(&normal) = primitive.attributes.get("NORMAL") {
+        let (_, normals) = read_attribute(document, buffers, normal, "NORMAL", &[3])?;
+        check_count("NORMAL", normal, normals.len() / 3)?;
+        mesh.normals = normals
+            .chunks(3)
+            .map(|n| Vec3::new(n[0] as f32, n[1] as f32, n[2] as f32))
+            .collect();
+    }
+    if let Some(&uv) = primitive.attributes.get("TEXCOORD_0") {
+        let (_, uvs) = read_attribute(document, buffers, uv, "TEXCOORD_0", &[2])?;
+        check_count("TEXCOORD_0", uv, uvs.len() / 2)?;
+        mesh.uvs = uvs
+            .chunks(2)
+            .map(|uv| Vec2::new(uv[0] as f32, uv[1] as f32))
+            .collect();
+    }
+    if let Some(&color) = primitive.attributes.get("COLOR_0") {
+        let (components, colors) = read_attribute(document, buffers, color, "COLOR_0", &[3, 4])?;
+        check_count("COLOR_0", color, colors.len() / components)?;
+        mesh.vertex_colors = colors
+            .chunks(components)
+            .map(|c| {
+                let alpha = if components == 4 { c[3] } else { 1.0 };
+                color_from_factor([c[0], c[1], c[2], alpha])
+            })
+            .collect();
+    }
+
+    if let Some(index) = primitive.material {
+        let doc_material = document
+            .materials
+            .get(index)
+            .ok_or(format!("missing material {}", index))?;
+        mesh.material = Some(read_material(document, buffers, base, doc_material, index)?);
+    }
+
+    let indices: Vec<usize> = match primitive.indices {
+        Some(accessor) => read_attribute(document, buffers, accessor, "indices", &[1])?
+            .1
+            .into_iter()
+            .map(|index| index as usize)
+            .collect(),
+        None => (0..mesh.vertices.len()).collect(),
+    };
+    if let Some(index) = indices.iter().find(|&&index| index >= mesh.vertices.len()) {
+        return Err(format!("primitive references missing vertex {}", index));
+    }
+
+    let triangles: Vec<(usize, usize, usize)> = match mode {
+        4 => indices
+            .chunks_exact(3)
+            .map(|t| (t[0], t[1], t[2]))
+            .collect(),
+        // strips flip every other triangle to keep the winding
+        5 => (0..indices.len().saturating_sub(2))
+            .map(|i| {
+                if i % 2 == 0 {
+                    (indices[i], indices[i + 1], indices[i + 2])
+                } else {
+                    (indices[i + 1], indices[i], indices[i + 2])
+                }
+            })
+            .collect(),
+        _ => (1..indices.len().saturating_sub(1))
+            .map(|i| (indices[0], indices[i], indices[i + 1]))
+            .collect(),
+    };
+
+    let base_color = mesh
+        .material
+        .as_ref()
+        .map_or(Color::RGBA(240, 240, 240, 255), |material| {
+            material.base_color
+        });
+    for (a, b, c) in triangles {
+        let color = if mesh.vertex_colors.is_empty() {
+            base_color
+        } else {
+            let vertex_color = mesh::average_color(&[
+                mesh.vertex_colors[a],
+                mesh.vertex_colors[b],
+                mesh.vertex_colors[c],
+            ]);
+            modulate_color(base_color, vertex_color)
+        };
+        // face indices are 1-based like in obj files
+        mesh.faces.push(Face::new(a + 1, b + 1, c + 1, color));
+    }
+    Ok(Some(mesh))
+}
+
+fn read_material(
+    document: &Document,
+    buffers: &[Vec<u8>],
+    base: &Path,
+    doc_material: &DocMaterial,
+    index: usize,
+) -> Result<Material, String> {
+    let pbr = doc_material.pbr_metallic_roughness.as_ref();
+    let factor = pbr
+        .and_then(|pbr| pbr.base_color_factor)
+        .unwrap_or([1.0, 1.0, 1.0, 1.0]);
+    let name = doc_material
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("material{}", index));
+
+    let mut material = Material::new(&name, color_from_factor(factor.map(|c| c as f64)));
+    // the alpha of the factor is only coverage in blend mode
+    if doc_material.alpha_mode.as_deref() == Some("BLEND") {
+        material.opacity = factor[3].clamp(0.0, 1.0);
+    }
+    if let Some(texture) = pbr.and_then(|pbr| pbr.base_color_texture.as_ref()) {
+        let image = document
+            .textures
+            .get(texture.index)
+            .and_then(|texture| texture.source)
+            .and_then(|source| document.images.get(source))
+            .ok_or(format!("material {} references a missing texture", index))?;
+        let image = read_image(document, buffers, base, image)?;
+        material.texture = Texture::decode(&image)
+            .map_err(|e| format!("material {}: {}", index, e))?
+            .map(Arc::new);
+        material.base_color_texture = Some(image);
+    }
+    Ok(material)
+}
+
+fn read_image(
+    document: &Document,
+    buffers: &[Vec<u8>],
+    base: &Path,
+    image: &DocImage,
+) -> Result<Image, String> {
+    let mime_type = image.mime_type.clone().unwrap_or_default();
+    match (&image.uri, image.buffer_view) {
+        (Some(uri), _) if uri.starts_with("data:") => Ok(Image::Embedded {
+            mime_type: uri[5..]
+                .split([';', ','])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            data: decode_data_uri(uri)?,
+        }),
+        (Some(uri), _) => Ok(Image::Uri(base.join(uri))),
+        (None, Some(view)) => {
+            let view = document
+                .buffer_views
+                .get(view)
+                .ok_or(format!("missing bufferView {}", view))?;
+            let data = view_data(buffers, view).ok_or("image bufferView is out of bounds")?;
+            Ok(Image::Embedded {
+                mime_type,
+                data: data.to_vec(),
+            })
+        }
+        (None, None) => Err("image has neither uri nor bufferView".to_string()),
+    }
+}
+
+// Reads an accessor of a vertex attribute or of the indices, which must have one of the
+// `components` counts, returning the number of components per element
+fn read_attribute(
+    document: &Document,
+    buffers: &[Vec<u8>],
+    index: usize,
+    name: &str,
+    components: &[usize],
+) -> Result<(usize, Vec<f64>), String> {
+    let (count, values) = read_accessor(document, buffers, index)?;
+    if !components.contains(&count) {
+        return Err(format!(
+            "{} accessor {} has {} components per element, expected {:?}",
+            name, index, count, components
+        ));
+    }
+    Ok((count, values))
+}
+
+// Bytes of a buffer view, None when it is out of its buffer
+fn view_data<'a>(buffers: &'a [Vec<u8>], view: &DocBufferView) -> Option<&'a [u8]> {
+    let end = view.byte_offset.checked_add(view.byte_length)?;
+    buffers.get(view.buffer)?.get(view.byte_offset..end)
+}
+
+// Reads an accessor into a flat list of values, returning the number of components per element
+fn read_accessor(
+    document: &Document,
+    buffers: &[Vec<u8>],
+    index: usize,
+) -> Result<(usize, Vec<f64>), String> {
+    let accessor = document
+        .accessors
+        .get(index)
+        .ok_or(format!("missing accessor {}", index))?;
+    if accessor.sparse.is_some() {
+        return Err(format!(
+            "accessor {} is sparse, sparse accessors are not supported",
+            index
+        ));
+    }
+
+    let components = match accessor.ty.as_str() {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" | "MAT2" => 4,
+        "MAT3" => 9,
+        "MAT4" => 16,
+        other => return Err(format!("accessor {} has unknown type '{}'", index, other)),
+    };
+    let component_size = match accessor.component_type {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        other => {
+            return Err(format!(
+                "accessor {} has unknown componentType {}",
+                index, other
+            ))
+        }
+    };
+
+    // without a buffer view the values would be all zeros, only useful with the sparse
+    // values that aren't supported either
+    let view = match accessor.buffer_view {
+        Some(view) => document
+            .buffer_views
+            .get(view)
+            .ok_or(format!("missing bufferView {}", view))?,
+        None => return Err(format!("accessor {} has no bufferView", index)),
+    };
+    let data = view_data(buffers, view)
+        .ok_or(format!("bufferView of accessor {} is out of bounds", index))?;
+    let element_size = components * component_size;
+    let stride = view.byte_stride.unwrap_or(element_size);
+    if stride < element_size {
+        return Err(format!(
+            "bufferView of accessor {} has a byteStride smaller than its elements",
+            index
+        ));
+    }
+    // every element has to be in the view before anything is allocated for them
+    let end = match accessor.count.checked_sub(1) {
+        Some(last) => last
+            .checked_mul(stride)
+            .and_then(|offset| offset.checked_add(accessor.byte_offset))
+            .and_then(|offset| offset.checked_add(element_size)),
+        None => Some(accessor.byte_offset),
+    };
+    if end.is_none_or(|end| end > data.len()) {
+        return Err(format!("accessor {} is out of bounds", index));
+    }
+
+    let mut values = Vec::with_capacity(accessor.count * components);
+    for element in 0..accessor.count {
+        for component in 0..components {
+            let offset = accessor.byte_offset + element * stride + component * component_size;
+            let b = data
+                .get(offset..offset + component_size)
+                .ok_or(format!("accessor {} is out of bounds", index))?;
+            let value = match accessor.component_type {
+                5120 => b[0] as i8 as f64,
+                5121 => b[0] as f64,
+                5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            };
+            values.push(if accessor.normalized {
+                normalize_component(value, accessor.component_type)
+            } else {
+                value
+            });
+        }
+    }
+    Ok((components, values))
+}
+
+fn normalize_component(value: f64, component_type: u32) -> f64 {
+    match component_type {
+        5120 => (value / 127.0).max(-1.0),
+        5121 => value / 255.0,
+        5122 => (value / 32767.0).max(-1.0),
+        5123 => value / 65535.0,
+        5125 => value / u32::MAX as f64,
+        _ => value,
+    }
+}
+
+fn decompose_matrix(matrix: &[f32; 16], node: &mut Node) {
+    // glTF matrices are column-major, which matches our row-vector layout
+    let row = |i: usize| Vec3::new(matrix[i * 4], matrix[i * 4 + 1], matrix[i * 4 + 2]);
+    node.translation = row(3);
+    node.scale = Vec3::new(row(0).len(), row(1).len(), row(2).len());
+
+    let (x, y, z) = (row(0).normalize(), row(1).normalize(), row(2).normalize());
+    // r[i][j] is the column-vector rotation matrix, the transpose of ours
+    let r = [[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]];
+    let trace = r[0][0] + r[1][1] + r[2][2];
+    node.rotation = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [
+            (r[2][1] - r[1][2]) / s,
+            (r[0][2] - r[2][0]) / s,
+            (r[1][0] - r[0][1]) / s,
+            0.25 * s,
+        ]
+    } else if r[0][0] > r[1][1] && r[0][0] > r[2][2] {
+        let s = (1.0 + r[0][0] - r[1][1] - r[2][2]).sqrt() * 2.0;
+        [
+            0.25 * s,
+            (r[0][1] + r[1][0]) / s,
+            (r[0][2] + r[2][0]) / s,
+            (r[2][1] - r[1][2]) / s,
+        ]
+    } else if r[1][1] > r[2][2] {
+        let s = (1.0 + r[1][1] - r[0][0] - r[2][2]).sqrt() * 2.0;
+        [
+            (r[0][1] + r[1][0]) / s,
+            0.25 * s,
+            (r[1][2] + r[2][1]) / s,
+            (r[0][2] - r[2][0]) / s,
+        ]
+    } else {
+        let s = (1.0 + r[2][2] - r[0][0] - r[1][1]).sqrt() * 2.0;
+        [
+            (r[0][2] + r[2][0]) / s,
+            (r[1][2] + r[2][1]) / s,
+            0.25 * s,
+            (r[1][0] - r[0][1]) / s,
+        ]
+    };
+}
+
+fn color_from_factor(factor: [f64; 4]) -> Color {
+    let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color::RGBA(
+        channel(factor[0]),
+        channel(factor[1]),
+        channel(factor[2]),
+        channel(factor[3]),
+    )
+}
+
+fn modulate_color(a: Color, b: Color) -> Color {
+    let channel = |x: u8, y: u8| (x as u32 * y as u32 / 255) as u8;
+    Color::RGBA(
+        channel(a.r, b.r),
+        channel(a.g, b.g),
+        channel(a.b, b.b),
+        channel(a.a, b.a),
+    )
+}
+
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, String> {
+    match uri.split_once(',') {
+        Some((header, data)) if header.ends_with(";base64") => decode_base64(data),
+        _ => Err("only base64 data uris are supported".to_string()),
+    }
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut accumulator: u32 = 0;
+    let mut bits = 0;
+
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return Err(format!("invalid base64 character '{}'", c as char)),
+        };
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+// glTF JSON document, only the parts the renderer uses
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<DocScene>,
+    #[serde(default)]
+    nodes: Vec<DocNode>,
+    #[serde(default)]
+    meshes: Vec<DocMesh>,
+    #[serde(default)]
+    accessors: Vec<DocAccessor>,
+    #[serde(default)]
+    buffer_views: Vec<DocBufferView>,
+    #[serde(default)]
+    buffers: Vec<DocBuffer>,
+    #[serde(default)]
+    materials: Vec<DocMaterial>,
+    #[serde(default)]
+    textures: Vec<DocTexture>,
+    #[serde(default)]
+    images: Vec<DocImage>,
+}
+
+#[derive(Deserialize)]
+struct DocScene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct DocNode {
+    name: Option<String>,
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+struct DocMesh {
+    primitives: Vec<DocPrimitive>,
+}
+
+#[derive(Deserialize)]
+struct DocPrimitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocAccessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    ty: String,
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocBufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocBuffer {
+    uri: Option<String>,
+    byte_length: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocMaterial {
+    name: Option<String>,
+    pbr_metallic_roughness: Option<DocPbr>,
+    alpha_mode: Option<String>, // OPAQUE when missing
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocPbr {
+    base_color_factor: Option<[f32; 4]>,
+    base_color_texture: Option<DocTextureInfo>,
+}
+
+#[derive(Deserialize)]
+struct DocTextureInfo {
+    index: usize,
+}
+
+#[derive(Deserialize)]
+struct DocTexture {
+    source: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocImage {
+    uri: Option<String>,
+    mime_type: Option<String>,
+    buffer_view: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in bytes.chunks(3) {
+            let word = chunk.iter().enumerate().fold(0u32, |word, (i, &byte)| {
+                word | (byte as u32) << (16 - 8 * i)
+            });
+            for i in 0..=chunk.len() {
+                text.push(ALPHABET[(word >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        while !text.len().is_multiple_of(4) {
+            text.push('=');
+        }
+        text
+    }
+
+    // The four corners of a unit square, then `indices` as u16 and `colors` rgb colors,
+    // with the offsets of the indices and colors
+    fn square_buffer(indices: &[u16], colors: usize) -> (Vec<u8>, usize, usize) {
+        let mut data = Vec::new();
+        for corner in [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0f32],
+        ] {
+            data.extend(corner.iter().flat_map(|value| value.to_le_bytes()));
+        }
+        let index_offset = data.len();
+        data.extend(indices.iter().flat_map(|index| index.to_le_bytes()));
+        data.resize(data.len().next_multiple_of(4), 0);
+        let color_offset = data.len();
+        for _ in 0..colors {
+            data.extend(
+                [1.0, 0.5, 0.0f32]
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes()),
+            );
+        }
+        (data, index_offset, color_offset)
+    }
+
+    // A mesh of one primitive drawing the square, the buffer at `uri` or in the GLB
+    // binary chunk when None
+    fn square_document(uri: Option<String>, mode: u32, indices: &[u16], colors: usize) -> Value {
+        let (data, index_offset, color_offset) = square_buffer(indices, colors);
+        let mut attributes = json!({ "POSITION": 0 });
+        if colors > 0 {
+            attributes["COLOR_0"] = json!(2);
+        }
+        let mut buffer = json!({ "byteLength": data.len() });
+        if let Some(uri) = uri {
+            buffer["uri"] = json!(uri);
+        }
+        json!({
+            "buffers": [buffer],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": index_offset },
+                { "buffer": 0, "byteOffset": index_offset, "byteLength": indices.len() * 2 },
+                { "buffer": 0, "byteOffset": color_offset, "byteLength": colors * 12 },
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3" },
+                { "bufferView": 1, "componentType": 5123, "count": indices.len(), "type": "SCALAR" },
+                { "bufferView": 2, "componentType": 5126, "count": colors, "type": "VEC3" },
+            ],
+            "meshes": [{ "primitives": [{ "attributes": attributes, "indices": 1, "mode": mode }] }],
+            "nodes": [{ "mesh": 0 }],
+        })
+    }
+
+    fn load_json(document: &Value) -> Result<Mesh, String> {
+        let json = serde_json::to_vec(document).unwrap();
+        load_from_bytes(&json, Path::new(".")).map(Scene::into_mesh)
+    }
+
+    // Square drawn with `indices`, from a base64 data uri
+    fn load_square(mode: u32, indices: &[u16], colors: usize) -> Result<Mesh, String> {
+        let (data, ..) = square_buffer(indices, colors);
+        let uri = format!("data:application/octet-stream;base64,{}", base64(&data));
+        load_json(&square_document(Some(uri), mode, indices, colors))
+    }
+
+    fn corners(mesh: &Mesh) -> Vec<(usize, usize, usize)> {
+        mesh.faces
+            .iter()
+            .map(|face| (face.a, face.b, face.c))
+            .collect()
+    }
+
+    #[test]
+    fn base64_buffer() {
+        let mesh = load_square(4, &[0, 1, 2, 0, 2, 3], 0).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!((mesh.vertices[2].x, mesh.vertices[2].y), (1.0, 1.0));
+        assert_eq!(corners(&mesh), [(1, 2, 3), (1, 3, 4)]);
+    }
+
+    #[test]
+    fn glb_binary_chunk() {
+        let indices = [0, 1, 2, 0, 2, 3];
+        let (data, ..) = square_buffer(&indices, 0);
+        let mut json = serde_json::to_vec(&square_document(None, 4, &indices, 0)).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + data.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(&json);
+        glb.extend((data.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(&data);
+
+        let mesh = load_from_bytes(&glb, Path::new("."))
+            .map(Scene::into_mesh)
+            .unwrap();
+        assert_eq!(corners(&mesh), [(1, 2, 3), (1, 3, 4)]);
+    }
+
+    #[test]
+    fn strips_and_fans() {
+        // every other triangle of a strip is flipped to keep the winding
+        let strip = load_square(5, &[0, 1, 3, 2], 0).unwrap();
+        assert_eq!(corners(&strip), [(1, 2, 4), (4, 2, 3)]);
+        let fan = load_square(6, &[0, 1, 2, 3], 0).unwrap();
+        assert_eq!(corners(&fan), [(1, 2, 3), (1, 3, 4)]);
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let error = load_square(4, &[0, 1, 4], 0).err().unwrap();
+        assert!(error.contains("missing vertex 4"), "{}", error);
+    }
+
+    #[test]
+    fn attribute_count_must_match_positions() {
+        let mesh = load_square(4, &[0, 1, 2], 4).unwrap();
+        assert_eq!(mesh.vertex_colors.len(), 4);
+        let error = load_square(4, &[0, 1, 2], 3).err().unwrap();
+        assert!(
+            error.contains("COLOR_0 accessor 2 has 3 elements"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn png_base_color_texture() {
+        // 2 by 1 texels, red on the left and blue on the right
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+        writer.finish().unwrap();
+
+        let indices = [0, 1, 2];
+        let (data, ..) = square_buffer(&indices, 0);
+        let uri = format!("data:application/octet-stream;base64,{}", base64(&data));
+        let mut document = square_document(Some(uri), 4, &indices, 0);
+        document["meshes"][0]["primitives"][0]["material"] = json!(0);
+        document["materials"] =
+            json!([{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }]);
+        document["textures"] = json!([{ "source": 0 }]);
+        document["images"] = json!([{ "uri": format!("data:image/png;base64,{}", base64(&png)) }]);
+
+        let mesh = load_json(&document).unwrap();
+        let texture = mesh.material.unwrap().texture.unwrap();
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(texture.sample(Vec2::new(0.25, 0.5)), Color::RGB(255, 0, 0));
+        assert_eq!(texture.sample(Vec2::new(0.75, 0.5)), Color::RGB(0, 0, 255));
+        // other formats are kept for export without being decoded
+        let jpeg = Image::Embedded {
+            mime_type: "image/jpeg".to_string(),
+            data: vec![0xFF, 0xD8, 0xFF],
+        };
+        assert!(Texture::decode(&jpeg).unwrap().is_none());
+    }
+}
@@ -0,0 +1,113 @@
+// Scene graph of nodes with parent-child transforms
+use std::rc::Rc;
+
+use crate::gltf;
+use crate::instance::{MeshInstance, Model};
+use crate::matrix::Matrix;
+use crate::vector::Vec3;
+
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    pub translation: Vec3,
+    pub spin: Vec3, // added to the rotation every second
+}
+
+impl Node {
+    /// Matrix that scales, rotates and translates the node relative to its parent
+    pub fn local_matrix(&self) -> Matrix {
+        Matrix::world(self.scale, self.rotation, self.translation)
+    }
+}
+
+// Nodes are stored after their parent, so world matrices can be computed in one pass
+#[derive(Default)]
+pub struct SceneGraph {
+    pub nodes: Vec<Node>,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph::default()
+    }
+
+    /// Add a node with an identity transform under `parent`, which must already exist
+    pub fn add_node(&mut self, name: &str, parent: Option<usize>) -> usize {
+        assert!(parent.is_none_or(|parent| parent < self.nodes.len()));
+        self.nodes.push(Node {
+            name: name.to_string(),
+            parent,
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            spin: Vec3::new(0.0, 0.0, 0.0),
+        });
+        self.nodes.len() - 1
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// World matrix of every node, its local matrix followed by its parent's world matrix
+    pub fn world_matrices(&self) -> Vec<Matrix> {
+        let mut matrices = Vec::with_capacity(self.nodes.len());
+        self.world_matrices_into(&mut matrices);
+        matrices
+    }
+
+    /// Same as `world_matrices`, reusing the memory of `matrices`
+    pub fn world_matrices_into(&self, matrices: &mut Vec<Matrix>) {
+        matrices.clear();
+        for node in &self.nodes {
+            let local = node.local_matrix();
+            let world = match node.parent {
+                Some(parent) => local.multiply_matrix(&matrices[parent]),
+                None => local,
+            };
+            matrices.push(world);
+        }
+    }
+
+    /// Add the node hierarchy of a glTF scene under `parent`, returning an instance of
+    /// `models[i]` for every node using glTF mesh `i`
+    pub fn add_gltf(
+        &mut self,
+        scene: &gltf::Scene,
+        models: &[Rc<Model>],
+        parent: Option<usize>,
+    ) -> Vec<MeshInstance> {
+        let mut instances = Vec::new();
+        for &root in &scene.roots {
+            self.add_gltf_node(scene, root, models, parent, &mut instances);
+        }
+        instances
+    }
+
+    fn add_gltf_node(
+        &mut self,
+        scene: &gltf::Scene,
+        index: usize,
+        models: &[Rc<Model>],
+        parent: Option<usize>,
+        instances: &mut Vec<MeshInstance>,
+    ) {
+        let gltf_node = &scene.nodes[index];
+        let id = self.add_node(&gltf_node.name, parent);
+        let node = &mut self.nodes[id];
+        node.rotation = gltf_node.euler_rotation();
+        node.scale = gltf_node.scale;
+        node.translation = gltf_node.translation;
+
+        if let Some(model) = gltf_node.mesh.and_then(|mesh| models.get(mesh)) {
+            let mut instance = MeshInstance::new(Rc::clone(model));
+            instance.node = Some(id);
+            instances.push(instance);
+        }
+        for &child in &gltf_node.children {
+            self.add_gltf_node(scene, child, models, Some(id), instances);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Mesh instances sharing the same mesh data
+use std::rc::Rc;
+use std::sync::Arc;
+
+use sdl2::pixels::Color;
+
+use crate::bounds::{BoundingSphere, FaceChunk};
+use crate::material::{Material, Texture};
+use crate::matrix::Matrix;
+use crate::mesh::Mesh;
+use crate::render_state::{BlendMode, RenderState};
+use crate::shader::Program;
+use crate::vector::Vec3;
+
+// Faces per chunk when culling parts of large meshes
+const FACES_PER_CHUNK: usize = 1024;
+
+// Mesh data with the bounds used for culling, computed once for every instance
+pub struct Model {
+    pub mesh: Mesh,
+    pub bounds: Option<BoundingSphere>,
+    pub chunks: Vec<FaceChunk>,
+}
+
+impl Model {
+    pub fn new(mesh: Mesh) -> Model {
+        Model {
+            bounds: mesh.bounding_sphere(),
+            chunks: mesh.face_chunks(FACES_PER_CHUNK),
+            mesh,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MeshInstance {
+    pub model: Rc<Model>,
+    pub node: Option<usize>, // scene graph node the transform is relative to
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    pub translation: Vec3,
+    pub spin: Vec3,                 // added to the rotation every second
+    pub material: Option<Material>, // replaces the face colors of the mesh when set
+    pub visible: bool,
+    pub program: Option<Rc<dyn Program>>, // draws the instance in shaded mode, lambert when None
+    pub render_state: Option<RenderState>, // replaces the renderer's state in shaded mode
+}
+
+impl MeshInstance {
+    /// Instance starting with the transform the mesh was loaded with
+    pub fn new(model: Rc<Model>) -> MeshInstance {
+        MeshInstance {
+            rotation: model.mesh.rotation,
+            scale: model.mesh.scale,
+            translation: model.mesh.translation,
+            spin: Vec3::new(0.0, 0.0, 0.0),
+            material: None,
+            visible: true,
+            program: None,
+            render_state: None,
+            node: None,
+            model,
+        }
+    }
+
+    /// Matrix that scales, rotates and translates the instance, in that order, and then
+    /// applies the world matrix of its node from `node_matrices`
+    pub fn world_matrix(&self, node_matrices: &[Matrix]) -> Matrix {
+        let local = Matrix::world(self.scale, self.rotation, self.translation);
+        match self.node {
+            Some(node) => local.multiply_matrix(&node_matrices[node]),
+            None => local,
+        }
+    }
+
+    /// Bounding sphere of the instance in world space
+    pub fn bounding_sphere(&self, node_matrices: &[Matrix]) -> Option<BoundingSphere> {
+        self.model
+            .bounds
+            .map(|sphere| sphere.transform(&self.world_matrix(node_matrices)))
+    }
+
+    /// Color of the material replacing the colors of the faces, if there is one
+    pub fn base_color(&self) -> Option<Color> {
+        self.material.as_ref().map(|material| material.base_color)
+    }
+
+    /// Texture of the material the faces are blended with, sampled in shaded mode
+    pub fn texture(&self) -> Option<Arc<Texture>> {
+        self.blend_material()
+            .and_then(|material| material.texture.clone())
+    }
+
+    /// Material the faces are blended with: the instance's own, or else the mesh's
+    pub fn blend_material(&self) -> Option<&Material> {
+        self.material.as_ref().or(self.model.mesh.material.as_ref())
+    }
+
+    /// Opacity and blend mode of the faces, opaque without a material
+    pub fn blending(&self) -> (f32, BlendMode) {
+        self.blend_material()
+            .map_or((1.0, BlendMode::Alpha), |material| {
+                (material.opacity, material.blend)
+            })
+    }
+
+    /// Whether the instance is drawn after the opaque ones
+    pub fn is_transparent(&self) -> bool {
+        self.blend_material()
+            .is_some_and(|material| material.is_transparent())
+    }
+}
+
+/// `count` instances of the model on a square grid facing the camera, spaced so
+/// they don't overlap
+pub fn grid(model: &Rc<Model>, count: usize) -> Vec<MeshInstance> {
+    let columns = (count as f32).sqrt().ceil() as usize;
+    let rows = count.div_ceil(columns.max(1));
+    let instance = MeshInstance::new(Rc::clone(model));
+    let spacing = instance
+        .bounding_sphere(&[])
+        .map_or(1.0, |sphere| sphere.radius * 2.5);
+
+    (0..count)
+        .map(|i| {
+            let mut copy = instance.clone();
+            let (column, row) = ((i % columns) as f32, (i / columns) as f32);
+            copy.translation = copy.translation
+                + Vec3::new(
+                    (column - (columns - 1) as f32 / 2.0) * spacing,
+                    ((rows - 1) as f32 / 2.0 - row) * spacing,
+                    0.0,
+                );
+            copy
+        })
+        .collect()
+}
@@ -0,0 +1,29 @@
+// Modules shared by the renderer binary and the benches
+pub mod animation;
+pub mod bounds;
+pub mod cli;
+pub mod display;
+pub mod export;
+pub mod frustum;
+pub mod gizmo;
+pub mod gltf;
+pub mod graph;
+pub mod instance;
+pub mod light;
+pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod normals;
+pub mod oit;
+pub mod pipeline;
+pub mod primitives;
+pub mod render_state;
+pub mod scene;
+pub mod shader;
+pub mod shadow;
+pub mod simd;
+pub mod timing;
+pub mod triangle;
+pub mod validation;
+pub mod vector;
+pub mod vertex_cache;
@@ -0,0 +1,160 @@
+// Lights of a scene and the diffuse lighting they give a surface
+use sdl2::pixels::Color;
+use serde::Deserialize;
+
+use crate::matrix::Matrix;
+use crate::shadow::Shadows;
+use crate::vector::{Vec3, Vec4};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightKind {
+    Directional, // parallel rays along the direction, like the sun
+    Point,       // from the position in every direction
+    Spot,        // from the position in a cone around the direction
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub direction: Vec3, // where directional and spot lights shine to
+    pub position: Vec3,  // of point and spot lights
+    pub color: Color,
+    pub intensity: f32,
+    pub range: Option<f32>, // point and spot lights fade out to nothing at this distance
+    pub inner_cone: f32,    // half angle in radians, spot lights are full inside it
+    pub outer_cone: f32,    // and dark outside this one
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            direction,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            color: Color::RGB(255, 255, 255),
+            intensity,
+            range: None,
+            inner_cone: 0.0,
+            outer_cone: std::f32::consts::PI,
+        }
+    }
+
+    pub fn point(position: Vec3, intensity: f32, range: Option<f32>) -> Light {
+        Light {
+            kind: LightKind::Point,
+            position,
+            range,
+            ..Light::directional(Vec3::new(0.0, 0.0, 1.0), intensity)
+        }
+    }
+
+    /// Spot light with cones of `inner_cone` and `outer_cone` half angles, in radians
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        intensity: f32,
+        range: Option<f32>,
+        inner_cone: f32,
+        outer_cone: f32,
+    ) -> Light {
+        Light {
+            kind: LightKind::Spot,
+            position,
+            direction,
+            range,
+            inner_cone,
+            outer_cone,
+            ..Light::directional(direction, intensity)
+        }
+    }
+
+    /// The light moved by `matrix`, usually into view space
+    pub fn transform(&self, matrix: &Matrix) -> Light {
+        let mut matrix = *matrix;
+        let mut direction = Vec4::from_vec3(self.direction.normalize());
+        direction.w = 0.0;
+        Light {
+            direction: Vec3::from_vec4(matrix.multiply(&mut direction)),
+            position: matrix.transform(&self.position),
+            ..*self
+        }
+    }
+
+    /// Turn the direction by `yaw` around the world up axis and `pitch` up or down,
+    /// in radians, never past straight up or down
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        let direction = self.direction.normalize();
+        let limit = 89f32.to_radians();
+        let yaw = direction.x.atan2(direction.z) + yaw;
+        let pitch = (direction.y.asin() + pitch).clamp(-limit, limit);
+        self.direction = Vec3::new(
+            yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            yaw.cos() * pitch.cos(),
+        );
+    }
+
+    /// Color in [0, 1] per channel the light gives a surface at `position` facing
+    /// `normal`, both in the same space as the light
+    pub fn illuminate(&self, position: Vec3, normal: Vec3) -> Vec3 {
+        // direction the light travels in to reach the surface
+        let (to_surface, distance) = match self.kind {
+            LightKind::Directional => (self.direction, 0.0),
+            LightKind::Point | LightKind::Spot => {
+                let offset = position - self.position;
+                let distance = offset.len();
+                if distance <= f32::EPSILON {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                (offset / distance, distance)
+            }
+        };
+        let mut strength = (-normal.dot(to_surface)).clamp(0.0, 1.0) * self.intensity;
+        if let Some(range) = self.range {
+            // smooth fall off, nothing left at the range
+            let fraction = (distance / range).min(1.0);
+            strength *= (1.0 - fraction * fraction).powi(2);
+        }
+        if self.kind == LightKind::Spot {
+            let angle = to_surface
+                .dot(self.direction.normalize())
+                .clamp(-1.0, 1.0)
+                .acos();
+            let width = (self.outer_cone - self.inner_cone).max(f32::EPSILON);
+            strength *= ((self.outer_cone - angle) / width).clamp(0.0, 1.0);
+        }
+        Vec3::new(
+            self.color.r as f32,
+            self.color.g as f32,
+            self.color.b as f32,
+        ) * (strength / 255.0)
+    }
+}
+
+/// Color in [0, 1] per channel all the `lights` give a surface at `position` facing
+/// `normal`, on top of the `ambient` light reaching every surface. Lights are dimmed
+/// where `shadows` has something in their way
+pub fn illuminate(
+    lights: &[Light],
+    ambient: f32,
+    shadows: &Shadows,
+    position: Vec3,
+    normal: Vec3,
+) -> Vec3 {
+    let ambient = Vec3::new(ambient, ambient, ambient);
+    let sum = lights.iter().enumerate().fold(ambient, |sum, (i, light)| {
+        let light = light.illuminate(position, normal);
+        // only look the point up in the shadow maps when the light reaches it at all
+        if light.x + light.y + light.z > 0.0 {
+            sum + light * shadows.visibility(i, position)
+        } else {
+            sum
+        }
+    });
+    Vec3::new(
+        sum.x.clamp(0.0, 1.0),
+        sum.y.clamp(0.0, 1.0),
+        sum.z.clamp(0.0, 1.0),
+    )
+}
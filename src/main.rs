@@ -1,79 +1,163 @@
 extern crate sdl2;
 
-use display::FRAMES_PER_SECOND;
-use matrix::Matrix;
+use renderer_rs::matrix::Matrix;
+use renderer_rs::vector::Vec3;
+use renderer_rs::{
+    animation, bounds, cli, display, export, frustum, gizmo, graph, instance, light, oit, pipeline,
+    render_state, scene, shader, timing, triangle, vertex_cache,
+};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::Sdl;
-use std::{f32::consts::PI, time::Duration};
-use vector::{Vec2, Vec3, Vec4};
-mod display;
-mod matrix;
-mod mesh;
-mod triangle;
-mod vector;
-
-const LIGHT_DIRECTION: Vec3 = Vec3 {
-    x: 0.0,
-    y: 0.0,
-    z: 1.0,
-};
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::{EventPump, Sdl};
+use std::rc::Rc;
+
+// Seconds the arrow keys move the animation timeline
+const TIMELINE_STEP: f32 = 0.25;
+// Radians a light turns for every pixel the mouse is dragged
+const LIGHT_TURN: f32 = 0.01;
+// Light intensity a mouse wheel notch adds, and ambient level a key press adds
+const INTENSITY_STEP: f32 = 0.1;
+const AMBIENT_STEP: f32 = 0.05;
+// Color of the gizmo of the light the controls apply to
+const SELECTED_LIGHT_COLOR: sdl2::pixels::Color = sdl2::pixels::Color::RGB(255, 220, 0);
 
-struct Renderer {
-    sdl_context: Sdl,
+struct Renderer<'a> {
+    event_pump: EventPump,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    screen_texture: display::ScreenTexture<'a>,
     canvas: Canvas<Window>,
     is_running: bool,
-    color_buffer: Vec<u8>,
+    color_buffer: display::ColorBuffer,
+    depth_buffer: Vec<f32>,
     triangles_to_render: Vec<triangle::Triangle>,
-    camera_position: Vec3,
-    mesh: mesh::Mesh,
+    uniforms: shader::Uniforms, // of the instance drawn in shaded mode
+    vertex_cache: vertex_cache::VertexCache,
+    camera: scene::Camera,
+    view_matrix: Matrix,
+    lights: Vec<light::Light>,
+    view_lights: Vec<light::Light>, // in view space
+    node_matrices: Vec<Matrix>,     // world matrix of every graph node
+    selected_light: usize,          // turned, moved and dimmed with the mouse
+    ambient: f32,
+    show_gizmos: bool,
+    cast_shadows: bool,
+    background: sdl2::pixels::Color,
+    graph: graph::SceneGraph,
+    instances: Vec<instance::MeshInstance>,
+    timeline: animation::Timeline,
+    frustum: frustum::Frustum,
+    culled: (usize, usize), // meshes and chunks culled in the last frame
     render_method: display::RenderMethod,
     cull_method: display::CullMethod,
+    front_face: render_state::Winding,
+    facing_colors: bool,
+    transparency: display::Transparency,
+    weighted_buffer: oit::WeightedBuffer, // transparent fragments in weighted blended mode
     apply_light: bool,
     projection_matrix: Matrix,
 }
 
-impl Renderer {
-    pub fn new(window: Window, sdl_context: Sdl) -> Renderer {
-        let canvas = window
-            .into_canvas()
-            .present_vsync()
-            .accelerated()
-            .build()
-            .map_err(|e| e.to_string())
-            .unwrap();
-
-        let color_buffer = vec![0; (display::WINDOW_WIDTH * display::WINDOW_HEIGHT * 3) as usize];
-        let mesh = mesh::Mesh::load_from_file("./assets/f22.obj");
-        //let mesh = mesh::Mesh::new_cube();
-
-        // Initialize projection matrix
-        let fov = PI / 3.0; // 60 degrees
+impl<'a> Renderer<'a> {
+    pub fn new(
+        canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        sdl_context: Sdl,
+        scene: scene::Scene,
+    ) -> Renderer<'a> {
+        let color_buffer = display::ColorBuffer::new(
+            display::WINDOW_WIDTH,
+            display::WINDOW_HEIGHT,
+            scene.pixel_format,
+        );
+        let depth_buffer = vec![1.0; (display::WINDOW_WIDTH * display::WINDOW_HEIGHT) as usize];
+
+        // Initialize projection matrix, the view matrix follows the camera every frame
+        let camera = scene.camera;
         let aspect_ratio = display::WINDOW_HEIGHT as f32 / display::WINDOW_WIDTH as f32;
-        let near = 1.0;
-        let far = 100.0;
+        let projection_matrix =
+            Matrix::make_perspetive(camera.fov, aspect_ratio, camera.near, camera.far);
 
-        let projection_matrix = Matrix::make_perspetive(fov, aspect_ratio, near, far);
-        Renderer {
-            sdl_context,
+        let mut graph = scene.graph;
+        let mut instances = scene.instances;
+        if scene.frame_instances {
+            frame_instances(
+                &mut instances,
+                &mut graph,
+                camera.fov,
+                aspect_ratio,
+                camera.near,
+            );
+        }
+        let mut uniforms = shader::Uniforms::new();
+        if let Some(settings) = scene.shadows {
+            uniforms.shadows.settings = settings;
+        }
+        let mut renderer = Renderer {
+            event_pump: sdl_context.event_pump().unwrap(),
+            texture_creator,
+            screen_texture: display::ScreenTexture::new(),
             canvas,
             color_buffer,
+            depth_buffer,
             is_running: true,
-            camera_position: Vec3::new(0.0, 0.0, 0.0),
+            camera,
+            view_matrix: Matrix::identity(),
+            lights: scene.lights,
+            view_lights: Vec::new(),
+            node_matrices: Vec::new(),
+            selected_light: 0,
+            ambient: scene.ambient,
+            show_gizmos: false,
+            cast_shadows: scene.shadows.is_some(),
+            background: scene.background,
             triangles_to_render: Vec::new(),
-            mesh: mesh,
-            render_method: display::RenderMethod::Wireframe,
-            cull_method: display::CullMethod::None,
-            apply_light: true,
-            projection_matrix: projection_matrix,
-        }
+            uniforms,
+            vertex_cache: vertex_cache::VertexCache::new(),
+            graph,
+            instances,
+            timeline: scene.timeline,
+            frustum: frustum::Frustum::from_matrix(&projection_matrix),
+            culled: (0, 0),
+            render_method: scene.render_method,
+            cull_method: scene.cull_method,
+            front_face: scene.front_face,
+            facing_colors: scene.facing_colors,
+            transparency: scene.transparency,
+            weighted_buffer: oit::WeightedBuffer::new(
+                display::WINDOW_WIDTH,
+                display::WINDOW_HEIGHT,
+            ),
+            apply_light: scene.apply_light,
+            projection_matrix,
+        };
+        renderer.update_camera();
+        renderer.update_title();
+        renderer
+    }
+
+    // Recompute everything that depends on where the camera is
+    fn update_camera(&mut self) {
+        let camera = &self.camera;
+        self.view_matrix =
+            Matrix::look_at(camera.position, camera.target, Vec3::new(0.0, 1.0, 0.0));
+        // frustum planes in world space
+        self.frustum = frustum::Frustum::from_matrix(
+            &self.view_matrix.multiply_matrix(&self.projection_matrix),
+        );
+        self.view_lights.clear();
+        self.view_lights.extend(
+            self.lights
+                .iter()
+                .map(|light| light.transform(&self.view_matrix)),
+        );
     }
 
     pub fn process_input(&mut self) {
-        let mut events = self.sdl_context.event_pump().unwrap();
-        for event in events.poll_iter() {
+        // the light and ambient level shown in the title changed
+        let mut title_changed = false;
+        for event in self.event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => self.is_running = false,
                 Event::KeyDown {
@@ -88,138 +172,362 @@ impl Renderer {
                     Keycode::Num4 => {
                         self.render_method = display::RenderMethod::FillTriangleWireframe
                     }
+                    Keycode::Num7 => self.render_method = display::RenderMethod::Shaded,
                     // Cull methods
                     Keycode::Num5 => self.cull_method = display::CullMethod::None,
                     Keycode::Num6 => self.cull_method = display::CullMethod::CullBackface,
+                    Keycode::Num8 => self.cull_method = display::CullMethod::CullScreenBack,
+                    Keycode::Num9 => self.cull_method = display::CullMethod::CullScreenFront,
+                    Keycode::W => {
+                        self.front_face = match self.front_face {
+                            render_state::Winding::Clockwise => {
+                                render_state::Winding::CounterClockwise
+                            }
+                            render_state::Winding::CounterClockwise => {
+                                render_state::Winding::Clockwise
+                            }
+                        }
+                    }
+                    Keycode::F => self.facing_colors = !self.facing_colors,
+                    Keycode::T => {
+                        self.transparency = match self.transparency {
+                            display::Transparency::Sorted => display::Transparency::WeightedBlended,
+                            display::Transparency::WeightedBlended => display::Transparency::Sorted,
+                        }
+                    }
                     Keycode::L => self.apply_light = !self.apply_light,
-                    // to move the camera
+                    // Lights
+                    Keycode::Tab if !self.lights.is_empty() => {
+                        self.selected_light = (self.selected_light + 1) % self.lights.len();
+                        title_changed = true;
+                    }
+                    Keycode::Minus | Keycode::Equals => {
+                        let step = if keycode == Keycode::Minus {
+                            -AMBIENT_STEP
+                        } else {
+                            AMBIENT_STEP
+                        };
+                        self.ambient = (self.ambient + step).clamp(0.0, 1.0);
+                        title_changed = true;
+                    }
+                    Keycode::G => self.show_gizmos = !self.show_gizmos,
+                    Keycode::H => self.cast_shadows = !self.cast_shadows,
+                    // Animation timeline
+                    Keycode::Space => self.timeline.toggle_playing(),
+                    Keycode::Home => self.timeline.seek(0.0),
+                    Keycode::Left => self.timeline.seek(self.timeline.time - TIMELINE_STEP),
+                    Keycode::Right => self.timeline.seek(self.timeline.time + TIMELINE_STEP),
                     _ => {}
                 },
+                // Left drag turns the selected light, right drag moves it across the view
+                Event::MouseMotion {
+                    mousestate,
+                    xrel,
+                    yrel,
+                    ..
+                } => {
+                    let (dx, dy) = (xrel as f32, yrel as f32);
+                    if let Some(light) = self.lights.get_mut(self.selected_light) {
+                        if mousestate.left() && light.kind != light::LightKind::Point {
+                            light.rotate(dx * LIGHT_TURN, -dy * LIGHT_TURN);
+                        } else if mousestate.right() && light.kind != light::LightKind::Directional
+                        {
+                            move_light(light, &self.camera, &self.view_matrix, dx, dy);
+                        }
+                    }
+                }
+                Event::MouseWheel { y, .. } => {
+                    if let Some(light) = self.lights.get_mut(self.selected_light) {
+                        light.intensity = (light.intensity + y as f32 * INTENSITY_STEP).max(0.0);
+                        title_changed = true;
+                    }
+                }
                 _ => {}
             }
         }
+        if title_changed {
+            self.update_title();
+        }
     }
-    pub fn update(&mut self) {
-        // change the mesh roration/scale values per animation frame
-        self.mesh.rotation.x += 0.02;
-        self.mesh.rotation.y += 0.02;
-        self.mesh.rotation.z += 0.01;
-        self.mesh.translation.z = 5.0;
-
-        // Create Scale matrix that will be used to multiply the mesh vertices
-
-        let mut scale_matrix =
-            Matrix::new().scale(self.mesh.scale.x, self.mesh.scale.y, self.mesh.scale.z);
-
-        let mut translation_matrix = Matrix::new().translate(
-            self.mesh.translation.x,
-            self.mesh.translation.y,
-            self.mesh.translation.z,
+
+    // Show the meshes and chunks culled in the last frame, the selected light and the
+    // ambient level in the window title
+    fn update_title(&mut self) {
+        let mut title = format!(
+            "{} - culled meshes: {}, culled chunks: {}",
+            display::WINDOW_TITLE,
+            self.culled.0,
+            self.culled.1
         );
-        let mut rotation_matrix_x = Matrix::new().rotate_x(self.mesh.rotation.x);
-        let mut rotation_matrix_y = Matrix::new().rotate_y(self.mesh.rotation.y);
-        let mut rotation_matrix_z = Matrix::new().rotate_z(self.mesh.rotation.z);
-
-        let num_faces = self.mesh.faces.len();
-        for i in 0..num_faces {
-            let cube_face = self.mesh.faces[i];
-
-            let mut face_vertices: [Vec3; 3] = [Vec3::new(0.0, 0.0, 0.0); 3];
-            face_vertices[0] = self.mesh.vertices[cube_face.a - 1];
-            face_vertices[1] = self.mesh.vertices[cube_face.b - 1];
-            face_vertices[2] = self.mesh.vertices[cube_face.c - 1];
-
-            let mut transformed_vertices: [Vec4; 3] = [Vec4::new(0.0, 0.0, 0.0, 0.0); 3];
-
-            // Transforming vertices
-            for j in 0..3 {
-                let mut transformed_vertex = Vec4::from_vec3(face_vertices[j]);
-                // Use a matrix to scale, rotate, and translate the mesh
-                transformed_vertex = scale_matrix.multiply(&mut transformed_vertex);
-                transformed_vertex = rotation_matrix_x.multiply(&mut transformed_vertex);
-                transformed_vertex = rotation_matrix_y.multiply(&mut transformed_vertex);
-                transformed_vertex = rotation_matrix_z.multiply(&mut transformed_vertex);
-                transformed_vertex = translation_matrix.multiply(&mut transformed_vertex);
-                // Store transformed vertex
-                transformed_vertices[j] = transformed_vertex;
+        if let Some(light) = self.lights.get(self.selected_light) {
+            title += &format!(
+                " - light {}: {:?}, intensity {:.2}",
+                self.selected_light + 1,
+                light.kind,
+                light.intensity
+            );
+        }
+        title += &format!(" - ambient {:.2}", self.ambient);
+        // only fails on titles with nul bytes
+        let _ = self.canvas.window_mut().set_title(&title);
+    }
+
+    // Draw the light gizmos over the frame
+    fn draw_gizmos(&mut self) {
+        let camera = &self.camera;
+        let size = (camera.target - camera.position).len() * 0.25;
+        let mut gizmos = gizmo::Gizmos {
+            color_buffer: &mut self.color_buffer,
+            stage: pipeline::VertexStage {
+                model_view: self.view_matrix,
+                projection: self.projection_matrix,
+            },
+            near: camera.near,
+        };
+        for (i, light) in self.lights.iter().enumerate() {
+            let color = if i == self.selected_light {
+                SELECTED_LIGHT_COLOR
+            } else {
+                light.color
+            };
+            gizmos.light(light, camera.target, size, color);
+        }
+    }
+
+    // Advance the animation by `seconds`
+    pub fn update(&mut self, seconds: f32) {
+        self.timeline.advance(seconds);
+        self.timeline.apply(&mut self.graph, &mut self.camera);
+
+        // change the node and instance rotation values
+        for node in self.graph.nodes.iter_mut() {
+            node.rotation = node.rotation + node.spin * seconds;
+        }
+        for instance in self.instances.iter_mut() {
+            instance.rotation = instance.rotation + instance.spin * seconds;
+        }
+    }
+
+    // Draw the shadow maps of the lights from the visible instances, after the camera
+    // and the graph are updated
+    fn update_shadows(&mut self) {
+        let shadows = &mut self.uniforms.shadows;
+        if !self.cast_shadows || !self.apply_light {
+            shadows.clear();
+            return;
+        }
+        let mut casters = Vec::new();
+        let mut spheres = Vec::new();
+        for instance in self.instances.iter().filter(|instance| instance.visible) {
+            let world_matrix = instance.world_matrix(&self.node_matrices);
+            spheres.extend(
+                instance
+                    .model
+                    .bounds
+                    .map(|sphere| sphere.transform(&world_matrix)),
+            );
+            casters.push((&instance.model.mesh, world_matrix));
+        }
+        match bounds::BoundingSphere::enclosing(&spheres) {
+            Some(bounds) => shadows.render(&self.lights, &casters, &bounds, &self.view_matrix),
+            None => shadows.clear(),
+        }
+    }
+
+    // Transform, light and project the scene into the triangles to render
+    fn project_scene(&mut self) {
+        self.update_camera();
+        self.graph.world_matrices_into(&mut self.node_matrices);
+        self.update_shadows();
+        let mut triangles = std::mem::take(&mut self.triangles_to_render);
+        let mut cache = std::mem::take(&mut self.vertex_cache);
+        let mut culled = (0, 0);
+        for instance in self.instances.iter().filter(|instance| instance.visible) {
+            let world_matrix = instance.world_matrix(&self.node_matrices);
+            self.project_instance(
+                instance,
+                &world_matrix,
+                &mut triangles,
+                &mut cache,
+                &mut culled,
+            );
+        }
+        if culled != self.culled {
+            self.culled = culled;
+            self.update_title();
+        }
+        self.triangles_to_render = triangles;
+        self.vertex_cache = cache;
+
+        // Sorting triangles by depth. Without a depth buffer every triangle has to be
+        // drawn after the ones behind it, so transparent triangles are sorted together
+        // with the opaque ones
+        self.triangles_to_render
+            .sort_by(|a, b| b.avg_depth.total_cmp(&a.avg_depth));
+    }
+
+    // Transform, light and project the faces of an instance, counting the culled
+    // meshes and chunks
+    fn project_instance(
+        &self,
+        instance: &instance::MeshInstance,
+        world_matrix: &Matrix,
+        triangles: &mut Vec<triangle::Triangle>,
+        cache: &mut vertex_cache::VertexCache,
+        culled: &mut (usize, usize),
+    ) {
+        let model = &instance.model;
+        let mesh = &model.mesh;
+        let vertex_stage = pipeline::VertexStage {
+            model_view: world_matrix.multiply_matrix(&self.view_matrix),
+            projection: self.projection_matrix,
+        };
+        let (opacity, blend) = instance.blending();
+        let face_stage = pipeline::FaceStage {
+            lights: &self.view_lights,
+            ambient: self.ambient,
+            shadows: &self.uniforms.shadows,
+            apply_light: self.apply_light,
+            state: render_state::RenderState {
+                blend,
+                ..self.cull_method.render_state(self.front_face)
+            },
+            front_face: self.front_face,
+            facing_colors: self.facing_colors,
+            base_color: instance.base_color(),
+            opacity,
+        };
+
+        // Skip the instance, or the chunks of it, that are entirely outside the view
+        let is_visible = |sphere: &bounds::BoundingSphere| {
+            self.frustum
+                .intersects_sphere(&sphere.transform(world_matrix))
+        };
+        if let Some(sphere) = &model.bounds {
+            if !is_visible(sphere) {
+                culled.0 += 1;
+                culled.1 += model.chunks.len();
+                return;
             }
-            let vector_a = Vec3::from_vec4(transformed_vertices[0]); //     A
-            let vector_b = Vec3::from_vec4(transformed_vertices[1]); //   /   \
-            let vector_c = Vec3::from_vec4(transformed_vertices[2]); //  C-----B
-
-            // Calculate Normal
-            let vector_ab = (vector_b - vector_a).normalize();
-            let vector_ac = (vector_c - vector_a).normalize();
-            let normal = vector_ab.cross(vector_ac).normalize();
-            let mut light_color = cube_face.color;
-
-            if self.apply_light {
-                let light_direction = LIGHT_DIRECTION.normalize();
-                let light_intensity = -normal.dot(light_direction);
-
-                // clamp light intensity to make sure it's between 0 and 1
-                let light_intensity = light_intensity.clamp(0.0, 1.0);
-                light_color = self.light_apply_intensity(light_intensity, cube_face.color);
+        }
+        let visible_chunks = model.chunks.iter().filter(|chunk| {
+            let visible = is_visible(&chunk.sphere);
+            if !visible {
+                culled.1 += 1;
             }
-            if self.cull_method == display::CullMethod::CullBackface {
-                // Calculate Camera Ray
-                let camera_ray = self.camera_position - vector_a;
+            visible
+        });
 
-                //  Calculate Camera Ray Dot Normal
-                let dot_normal_camera = normal.dot(camera_ray);
-                if dot_normal_camera < 0.0 {
+        pipeline::project_faces(
+            mesh,
+            visible_chunks.map(|chunk| chunk.faces.clone()),
+            &vertex_stage,
+            &face_stage,
+            cache,
+            triangles,
+        );
+    }
+
+    // Draw the instances with their shader programs into the color and depth buffers
+    fn shade_scene(&mut self) {
+        self.update_camera();
+        self.graph.world_matrices_into(&mut self.node_matrices);
+        self.update_shadows();
+        display::clear_depth_buffer(&mut self.depth_buffer);
+        self.uniforms.projection = self.projection_matrix;
+        self.uniforms.lights.clone_from(&self.view_lights);
+        self.uniforms.ambient = self.ambient;
+        self.uniforms.apply_light = self.apply_light;
+        self.uniforms.facing_colors = self.facing_colors;
+
+        // the cull method keys apply to instances without a render state of their own
+        let state = self.cull_method.render_state(self.front_face);
+        let lambert = shader::lambert();
+        let mut target = shader::Target {
+            color_buffer: &mut self.color_buffer,
+            depth_buffer: &mut self.depth_buffer,
+            transparency: None,
+            width: display::WINDOW_WIDTH,
+            height: display::WINDOW_HEIGHT,
+        };
+        // Opaque instances first, then the transparent ones from back to front so each
+        // blends over everything behind it. They test against the depth buffer but
+        // don't write to it, so they don't hide each other
+        let mut transparent = Vec::new();
+        for (i, instance) in self.instances.iter().enumerate() {
+            if !instance.visible {
+                continue;
+            }
+            let world_matrix = instance.world_matrix(&self.node_matrices);
+            if let Some(sphere) = &instance.model.bounds {
+                if !self
+                    .frustum
+                    .intersects_sphere(&sphere.transform(&world_matrix))
+                {
                     continue;
                 }
             }
-            // Projecting 3D points to 2D
-            let mut projected_points = [Vec4::new(0.0, 0.0, 0.0, 0.0); 3];
-
-            for j in 0..3 {
-                projected_points[j] = self
-                    .projection_matrix
-                    .multiply_vec4_projection(&transformed_vertices[j]);
-                // Scaling projected point
-                projected_points[j].x *= display::WINDOW_WIDTH as f32 / 2.0;
-                projected_points[j].y *= display::WINDOW_HEIGHT as f32 / 2.0;
-
-                // Transforming projected point to screen space
-                projected_points[j].x += display::WINDOW_WIDTH as f32 / 2.0;
-                projected_points[j].y += display::WINDOW_HEIGHT as f32 / 2.0;
+            let model_view = world_matrix.multiply_matrix(&self.view_matrix);
+            if instance.is_transparent() {
+                let center = instance
+                    .model
+                    .bounds
+                    .map_or(Vec3::new(0.0, 0.0, 0.0), |sphere| sphere.center);
+                transparent.push((model_view.transform(&center).z, i, model_view));
+                continue;
             }
-            // Calculating average depth of triangle
-            let avg_depth =
-                (transformed_vertices[0].z + transformed_vertices[1].z + transformed_vertices[2].z)
-                    / transformed_vertices.len() as f32;
-
-            let projected_triangle: triangle::Triangle = triangle::Triangle {
-                points: [
-                    Vec2::new(projected_points[0].x, projected_points[0].y),
-                    Vec2::new(projected_points[1].x, projected_points[1].y),
-                    Vec2::new(projected_points[2].x, projected_points[2].y),
-                ],
-                color: light_color,
-                avg_depth: avg_depth,
-            };
-            // apply light intensity to triangle color
-            self.triangles_to_render.push(projected_triangle);
+            self.uniforms.set_model_view(model_view);
+            self.uniforms.base_color = instance.base_color();
+            self.uniforms.texture = instance.texture();
+            self.uniforms.opacity = 1.0;
+            let program: &dyn shader::Program = instance.program.as_deref().unwrap_or(&lambert);
+            let state = instance.render_state.as_ref().unwrap_or(&state);
+            program.draw(&mut target, &instance.model.mesh, state, &self.uniforms);
         }
-        // Sorting triangles by depth
-        self.triangles_to_render
-            .sort_by(|a, b| b.avg_depth.partial_cmp(&a.avg_depth).unwrap());
-    }
 
-    pub fn light_apply_intensity(
-        &mut self,
-        intensity: f32,
-        color: sdl2::pixels::Color,
-    ) -> sdl2::pixels::Color {
-        let r = (color.r as f32 * intensity) as u8;
-        let g = (color.g as f32 * intensity) as u8;
-        let b = (color.b as f32 * intensity) as u8;
-        sdl2::pixels::Color::RGBA(r, g, b, 255)
+        // Instances are sorted by the depth of their center, the triangles inside one
+        // transparent mesh are drawn in the order of its faces
+        transparent.sort_by(|a, b| b.0.total_cmp(&a.0));
+        // With weighted blended transparency the alpha blended instances are summed in
+        // any order and composited first, then the additive and multiplied ones blend
+        // over them in order
+        let weighted = self.transparency == display::Transparency::WeightedBlended;
+        if weighted {
+            self.weighted_buffer.clear();
+            target.transparency = Some(&mut self.weighted_buffer);
+        }
+        for order_independent in [true, false] {
+            for &(_, i, model_view) in &transparent {
+                let instance = &self.instances[i];
+                let (opacity, blend) = instance.blending();
+                // a render state of the instance's own keeps its culling and depth test,
+                // the material still decides the blending
+                let state = render_state::RenderState {
+                    depth_write: false,
+                    blend,
+                    ..instance.render_state.unwrap_or(state)
+                };
+                if (weighted && state.blend == render_state::BlendMode::Alpha) != order_independent
+                {
+                    continue;
+                }
+                self.uniforms.set_model_view(model_view);
+                self.uniforms.base_color = instance.base_color();
+                self.uniforms.texture = instance.texture();
+                self.uniforms.opacity = opacity;
+                let program: &dyn shader::Program = instance.program.as_deref().unwrap_or(&lambert);
+                program.draw(&mut target, &instance.model.mesh, &state, &self.uniforms);
+            }
+            target.composite_transparency();
+        }
     }
 
     pub fn render(&mut self) {
+        if self.render_method == display::RenderMethod::Shaded {
+            self.shade_scene();
+        } else {
+            self.project_scene();
+        }
         let num_triangles = self.triangles_to_render.len();
 
         for i in 0..num_triangles {
@@ -250,6 +558,7 @@ impl Renderer {
                         &mut self.color_buffer,
                         triangle.points,
                         triangle.color,
+                        triangle.blend,
                     );
                 }
                 // Draw filled triangle and then draw wireframe on top
@@ -258,6 +567,7 @@ impl Renderer {
                         &mut self.color_buffer,
                         triangle.points,
                         triangle.color,
+                        triangle.blend,
                     );
                     display::draw_triangle(
                         &mut self.color_buffer,
@@ -266,25 +576,151 @@ impl Renderer {
                         false,
                     );
                 }
+                // drawn by shade_scene, there are no triangles
+                display::RenderMethod::Shaded => {}
             }
         }
 
         self.triangles_to_render.clear();
-        display::render_color_buffer(&mut self.canvas, &mut self.color_buffer);
-        display::clear_color_buffer(&mut self.color_buffer);
+        if self.show_gizmos {
+            self.draw_gizmos();
+        }
+        self.screen_texture
+            .present(&mut self.canvas, self.texture_creator, &self.color_buffer);
+        display::clear_color_buffer(&mut self.color_buffer, self.background);
         self.canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / FRAMES_PER_SECOND));
+    }
+}
+
+// Move a light parallel to the screen by a mouse drag of `dx`, `dy` pixels, so it
+// follows the mouse
+fn move_light(
+    light: &mut light::Light,
+    camera: &scene::Camera,
+    view_matrix: &Matrix,
+    dx: f32,
+    dy: f32,
+) {
+    // the camera axes in world space are the columns of the view matrix
+    let m = &view_matrix.data;
+    let right = Vec3::new(m[0][0], m[1][0], m[2][0]);
+    let up = Vec3::new(m[0][1], m[1][1], m[2][1]);
+    // world units a pixel covers at the distance of the light
+    let distance = (light.position - camera.position).len();
+    let scale = 2.0 * distance * (camera.fov / 2.0).tan() / display::WINDOW_HEIGHT as f32;
+    light.position = light.position + right * (dx * scale) - up * (dy * scale);
+}
+
+// Move the instances together away from the camera until their bounding spheres
+// fit in the field of view and stay in front of the near plane
+fn frame_instances(
+    instances: &mut [instance::MeshInstance],
+    graph: &mut graph::SceneGraph,
+    fov: f32,
+    aspect_ratio: f32,
+    near: f32,
+) {
+    let node_matrices = graph.world_matrices();
+    let spheres: Vec<bounds::BoundingSphere> = instances
+        .iter()
+        .filter_map(|instance| instance.bounding_sphere(&node_matrices))
+        .collect();
+    let Some(bounds::BoundingSphere { center, radius }) =
+        bounds::BoundingSphere::enclosing(&spheres)
+    else {
+        return;
+    };
+
+    // fov is vertical, the projection widens it horizontally by 1 / aspect_ratio
+    let horizontal_fov = 2.0 * ((fov / 2.0).tan() / aspect_ratio).atan();
+    let half_fov = fov.min(horizontal_fov) / 2.0;
+    let distance = (radius / half_fov.sin()).max(radius + near);
+    let offset = Vec3::new(0.0, 0.0, distance) - center;
+    // moving the roots moves everything attached to them
+    for instance in instances
+        .iter_mut()
+        .filter(|instance| instance.node.is_none())
+    {
+        instance.translation = instance.translation + offset;
+    }
+    for node in graph.nodes.iter_mut().filter(|node| node.parent.is_none()) {
+        node.translation = node.translation + offset;
     }
 }
 
 pub fn main() {
+    let options = cli::Options::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+
+    if options.validate {
+        match options.load_mesh() {
+            Ok(mesh) => {
+                let report = mesh.validate();
+                print!("{}", report);
+                std::process::exit(if report.is_clean() { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(export_path) = &options.export_path {
+        let result = options
+            .load_mesh()
+            .and_then(|mesh| export::save(&mesh, export_path, options.bake_transform));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let scene = if options.is_scene() {
+        scene::Scene::load(&options.mesh_path)
+    } else {
+        options.load_mesh().map(|mut mesh| {
+            // instances rotate around the center of the mesh
+            mesh.recenter();
+            let model = Rc::new(instance::Model::new(mesh));
+            let mut instances = instance::grid(&model, options.instances);
+            for instance in instances.iter_mut() {
+                instance.spin = Vec3::new(1.2, 1.2, 0.6);
+            }
+            scene::Scene::from_instances(instances)
+        })
+    };
+    let mut scene = scene.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if options.rgba {
+        scene.pixel_format = display::PixelFormat::Rgba32;
+    }
+
     let sdl_context = sdl2::init().unwrap();
-    let window = display::initialize_window(&sdl_context);
-    let mut renderer = Renderer::new(window.unwrap(), sdl_context);
+    let window = display::initialize_window(&sdl_context).unwrap();
+    // vsync caps the loop to the display refresh rate, so it is only used when the frame
+    // cap is no higher than that; a refresh rate of 0 means it is unknown
+    let refresh_rate = window.display_mode().map_or(0, |mode| mode.refresh_rate);
+    let vsync = options
+        .max_fps
+        .is_some_and(|fps| refresh_rate > 0 && fps <= refresh_rate as u32);
+    let canvas = display::initialize_canvas(window, vsync).unwrap();
+    // the renderer borrows it, so it lives until the renderer is dropped
+    let texture_creator = canvas.texture_creator();
+    let mut renderer = Renderer::new(canvas, &texture_creator, sdl_context, scene);
+    let mut timer = timing::FrameTimer::new(options.max_fps);
 
     while renderer.is_running {
         renderer.process_input();
-        renderer.update();
+        for _ in 0..timer.tick() {
+            renderer.update(timing::FIXED_TIMESTEP);
+        }
         renderer.render();
+        timer.limit();
     }
 }
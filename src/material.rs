@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use sdl2::pixels::Color;
+
+use crate::render_state::BlendMode;
+use crate::vector::Vec2;
+
+// Where the pixels of a texture come from
+#[derive(Debug, Clone)]
+pub enum Image {
+    Uri(std::path::PathBuf),
+    Embedded { mime_type: String, data: Vec<u8> },
+}
+
+// Decoded pixels of an image, row by row from the top
+#[derive(Debug)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<Color>,
+}
+
+impl Texture {
+    /// Decode a PNG image, None for the other formats, which are only kept for export
+    pub fn decode(image: &Image) -> Result<Option<Texture>, String> {
+        let data = match image {
+            Image::Uri(path) => {
+                std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            Image::Embedded { data, .. } => data.clone(),
+        };
+        if !data.starts_with(b"\x89PNG") {
+            return Ok(None);
+        }
+        let mut decoder = png::Decoder::new(data.as_slice());
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        let channels = reader.output_color_type().0.samples();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(|e| e.to_string())?;
+        let texels = pixels[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|texel| match *texel {
+                [gray] => Color::RGB(gray, gray, gray),
+                [gray, alpha] => Color::RGBA(gray, gray, gray, alpha),
+                [r, g, b] => Color::RGB(r, g, b),
+                [r, g, b, a, ..] => Color::RGBA(r, g, b, a),
+                [] => Color::RGB(0, 0, 0),
+            })
+            .collect();
+        Ok(Some(Texture {
+            width: info.width,
+            height: info.height,
+            texels,
+        }))
+    }
+
+    /// Texel nearest to `uv`, repeating the image outside [0, 1]
+    pub fn sample(&self, uv: Vec2) -> Color {
+        let texel = |t: f32, size: u32| (((t - t.floor()) * size as f32) as u32).min(size - 1);
+        let (x, y) = (texel(uv.x, self.width), texel(uv.y, self.height));
+        self.texels[(y * self.width + x) as usize]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub base_color: Color,
+    pub base_color_texture: Option<Image>,
+    pub texture: Option<Arc<Texture>>, // the base color texture decoded, when it can be
+    pub opacity: f32,                  // 1 is opaque
+    pub blend: BlendMode,              // how the faces are combined with what is behind them
+}
+
+impl Material {
+    pub fn new(name: &str, base_color: Color) -> Material {
+        Material {
+            name: name.to_string(),
+            base_color,
+            base_color_texture: None,
+            texture: None,
+            opacity: 1.0,
+            blend: BlendMode::Alpha,
+        }
+    }
+
+    /// Whether what is behind the faces shows through them, so they are drawn after
+    /// the opaque ones
+    pub fn is_transparent(&self) -> bool {
+        self.blend.is_transparent(self.color())
+    }
+
+    /// Base color with the opacity as its alpha
+    pub fn color(&self) -> Color {
+        let mut color = self.base_color;
+        color.a = (self.opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        color
+    }
+}
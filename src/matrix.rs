@@ -1,10 +1,20 @@
 #![allow(dead_code)]
+use crate::simd;
 use crate::vector;
 
+// Rows are aligned for the SIMD code
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(16))]
 pub struct Matrix {
     pub data: [[f32; 4]; 4],
 }
 
+impl Default for Matrix {
+    fn default() -> Matrix {
+        Matrix::new()
+    }
+}
+
 impl Matrix {
     pub fn new() -> Matrix {
         Matrix {
@@ -34,29 +44,17 @@ impl Matrix {
     }
 
     pub fn multiply(&mut self, vector: &mut vector::Vec4) -> vector::Vec4 {
-        let mut result = vector::Vec4::new(0.0, 0.0, 0.0, 0.0);
-
-        result.x = vector.x * self.data[0][0]
-            + vector.y * self.data[1][0]
-            + vector.z * self.data[2][0]
-            + vector.w * self.data[3][0];
-        result.y = vector.x * self.data[0][1]
-            + vector.y * self.data[1][1]
-            + vector.z * self.data[2][1]
-            + vector.w * self.data[3][1];
-        result.z = vector.x * self.data[0][2]
-            + vector.y * self.data[1][2]
-            + vector.z * self.data[2][2]
-            + vector.w * self.data[3][2];
-        result.w = vector.x * self.data[0][3]
-            + vector.y * self.data[1][3]
-            + vector.z * self.data[2][3]
-            + vector.w * self.data[3][3];
+        let result = simd::transform(*vector, &self.data);
 
         *vector = result;
 
         result
     }
+
+    /// Multiply every vector of `vectors` by this matrix, in place
+    pub fn multiply_batch(&self, vectors: &mut [vector::Vec4]) {
+        simd::transform_batch(vectors, &self.data);
+    }
     // Translate matrix
     pub fn translate(&mut self, tx: f32, ty: f32, tz: f32) -> Matrix {
         // | 1   0   0   tx |
@@ -71,17 +69,9 @@ impl Matrix {
     }
     /// Transform a vector by this matrix
     pub fn transform(&self, v: &vector::Vec3) -> vector::Vec3 {
-        let mut result = vector::Vec3::new(0.0, 0.0, 0.0);
-
-        result.x =
-            v.x * self.data[0][0] + v.y * self.data[1][0] + v.z * self.data[2][0] + self.data[3][0];
-        result.y =
-            v.x * self.data[0][1] + v.y * self.data[1][1] + v.z * self.data[2][1] + self.data[3][1];
-        result.z =
-            v.x * self.data[0][2] + v.y * self.data[1][2] + v.z * self.data[2][2] + self.data[3][2];
-
-        let w =
-            v.x * self.data[0][3] + v.y * self.data[1][3] + v.z * self.data[2][3] + self.data[3][3];
+        let transformed = simd::transform(vector::Vec4::from_vec3(*v), &self.data);
+        let mut result = vector::Vec3::from_vec4(transformed);
+        let w = transformed.w;
 
         if w != 0.0 {
             result.x /= w;
@@ -130,6 +120,71 @@ impl Matrix {
         m
     }
 
+    /// View matrix of a camera at `eye` looking at `target`, moving the camera to the
+    /// origin looking down +z
+    pub fn look_at(eye: vector::Vec3, target: vector::Vec3, up: vector::Vec3) -> Matrix {
+        let forward = (target - eye).normalize();
+        let mut right = up.cross(forward);
+        if right.len() <= f32::EPSILON {
+            // looking straight along up, any perpendicular axis will do
+            right = vector::Vec3::new(1.0, 0.0, 0.0);
+        }
+        let right = right.normalize();
+        let up = forward.cross(right);
+
+        let mut m = Matrix::identity();
+        for (j, axis) in [right, up, forward].iter().enumerate() {
+            m.data[0][j] = axis.x;
+            m.data[1][j] = axis.y;
+            m.data[2][j] = axis.z;
+            m.data[3][j] = -axis.dot(eye);
+        }
+        m
+    }
+
+    /// Rotation around x, then y, then z
+    pub fn rotate_euler(rotation: vector::Vec3) -> Matrix {
+        Matrix::new()
+            .rotate_x(rotation.x)
+            .multiply_matrix(&Matrix::new().rotate_y(rotation.y))
+            .multiply_matrix(&Matrix::new().rotate_z(rotation.z))
+    }
+
+    /// Scale, then rotate and then translate
+    pub fn world(scale: vector::Vec3, rotation: vector::Vec3, translation: vector::Vec3) -> Matrix {
+        Matrix::new()
+            .scale(scale.x, scale.y, scale.z)
+            .multiply_matrix(&Matrix::rotate_euler(rotation))
+            .multiply_matrix(&Matrix::new().translate(translation.x, translation.y, translation.z))
+    }
+
+    /// Rotation matrix from a unit quaternion (x, y, z, w)
+    pub fn rotate_quaternion(x: f32, y: f32, z: f32, w: f32) -> Matrix {
+        let mut m = Matrix::identity();
+        m.data[0][0] = 1.0 - 2.0 * (y * y + z * z);
+        m.data[0][1] = 2.0 * (x * y + z * w);
+        m.data[0][2] = 2.0 * (x * z - y * w);
+        m.data[1][0] = 2.0 * (x * y - z * w);
+        m.data[1][1] = 1.0 - 2.0 * (x * x + z * z);
+        m.data[1][2] = 2.0 * (y * z + x * w);
+        m.data[2][0] = 2.0 * (x * z + y * w);
+        m.data[2][1] = 2.0 * (y * z - x * w);
+        m.data[2][2] = 1.0 - 2.0 * (x * x + y * y);
+        m
+    }
+
+    /// Combine two matrices, the result applies `self` first and then `other`
+    pub fn multiply_matrix(&self, other: &Matrix) -> Matrix {
+        // each row of the result is the row of `self` multiplied by `other`
+        let mut m = Matrix::new();
+        for (row, self_row) in m.data.iter_mut().zip(&self.data) {
+            let [x, y, z, w] = *self_row;
+            let result = simd::transform(vector::Vec4::new(x, y, z, w), &other.data);
+            *row = [result.x, result.y, result.z, result.w];
+        }
+        m
+    }
+
     pub fn make_perspetive(fov: f32, aspect_ratio: f32, near: f32, far: f32) -> Matrix {
         let mut m = Matrix {
             data: [[0.0; 4]; 4],
@@ -137,12 +192,66 @@ impl Matrix {
         m.data[0][0] = aspect_ratio * (1.0 / (fov / 2.0).tan());
         m.data[1][1] = 1.0 / (fov / 2.0).tan();
         m.data[2][2] = far / (far - near);
-        m.data[2][3] = (-far * near) / (far - near);
-        m.data[3][2] = 1.0;
+        // vectors multiply on the left, so z goes into w and the offset comes from w
+        m.data[2][3] = 1.0;
+        m.data[3][2] = (-far * near) / (far - near);
 
         m
     }
 
+    /// Orthographic projection of the box `half_width` by `half_height` around the
+    /// view axis between `near` and `far`, depths going from 0 to 1
+    pub fn make_orthographic(half_width: f32, half_height: f32, near: f32, far: f32) -> Matrix {
+        let mut m = Matrix::identity();
+        m.data[0][0] = 1.0 / half_width;
+        m.data[1][1] = 1.0 / half_height;
+        m.data[2][2] = 1.0 / (far - near);
+        m.data[3][2] = -near / (far - near);
+        m
+    }
+
+    /// Matrix undoing this one, None when it can't be undone
+    pub fn inverse(&self) -> Option<Matrix> {
+        // Gauss-Jordan elimination with partial pivoting, on the matrix and the
+        // identity side by side
+        let mut a = self.data;
+        let mut inverse = Matrix::identity().data;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() <= f32::EPSILON {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for row in 0..4 {
+                let factor = a[row][column];
+                if row == column || factor == 0.0 {
+                    continue;
+                }
+                for j in 0..4 {
+                    a[row][j] -= factor * a[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        Some(Matrix { data: inverse })
+    }
+
+    /// Rows swapped with columns
+    pub fn transpose(&self) -> Matrix {
+        Matrix {
+            data: [0, 1, 2, 3].map(|i| self.data.map(|row| row[i])),
+        }
+    }
+
     pub fn multiply_vec4_projection(&mut self, v: &vector::Vec4) -> vector::Vec4 {
         let mut result = self.multiply(&mut vector::Vec4::new(v.x, v.y, v.z, v.w));
         if result.w != 0.0 {
@@ -2,12 +2,21 @@ use std::io::Read;
 
 use sdl2::pixels::Color;
 
+use crate::gltf;
+use crate::material::Material;
+use crate::matrix::Matrix;
 use crate::triangle::Face;
 use crate::vector;
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<vector::Vec3>,
     pub faces: Vec<Face>,
+    // per-vertex attributes, empty when the source has none
+    pub vertex_colors: Vec<Color>,
+    pub normals: Vec<vector::Vec3>,
+    pub uvs: Vec<vector::Vec2>,
+    pub material: Option<Material>,
     // mesh attributes
     pub rotation: vector::Vec3,
     pub scale: vector::Vec3,
@@ -112,25 +121,78 @@ pub const CUBE_FACES: [Face; N_CUBE_FACES] = [
 ];
 impl Mesh {
     pub fn new_cube() -> Mesh {
-        let mut vertices: Vec<vector::Vec3> = Vec::new();
-        let mut faces: Vec<Face> = Vec::new();
-
-        for i in 0..N_CUBE_VERTICES {
-            vertices.push(CUBE_VERTICES[i]);
-        }
+        Mesh::from_geometry(CUBE_VERTICES.to_vec(), CUBE_FACES.to_vec())
+    }
 
-        for i in 0..N_CUBE_FACES {
-            faces.push(CUBE_FACES[i]);
-        }
+    /// Mesh with no extra vertex attributes and an identity transform
+    pub fn from_geometry(vertices: Vec<vector::Vec3>, faces: Vec<Face>) -> Mesh {
         Mesh {
-            vertices: vertices,
-            faces: faces,
+            vertices,
+            faces,
+            vertex_colors: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            material: None,
             rotation: vector::Vec3::new(0.0, 0.0, 0.0),
             scale: vector::Vec3::new(1.0, 1.0, 1.0),
             translation: vector::Vec3::new(0.0, 0.0, 0.0),
         }
     }
 
+    /// Matrix that rotates the mesh around x, then y, then z
+    pub fn rotation_matrix(&self) -> Matrix {
+        Matrix::rotate_euler(self.rotation)
+    }
+
+    /// Matrix that scales, rotates and translates the mesh, in that order
+    pub fn world_matrix(&self) -> Matrix {
+        Matrix::world(self.scale, self.rotation, self.translation)
+    }
+
+    /// Apply the mesh transform to the vertices and normals and reset it to identity
+    pub fn bake_transform(&mut self) {
+        let world = self.world_matrix();
+        for vertex in self.vertices.iter_mut() {
+            *vertex = world.transform(vertex);
+        }
+
+        // normals take the inverse scale so they stay perpendicular to the surface, an
+        // axis scaled to zero has no inverse and is left as it is
+        let mut rotation = self.rotation_matrix();
+        let inverse = |value: f32, scale: f32| if scale == 0.0 { value } else { value / scale };
+        for normal in self.normals.iter_mut() {
+            let mut n = vector::Vec4::new(
+                inverse(normal.x, self.scale.x),
+                inverse(normal.y, self.scale.y),
+                inverse(normal.z, self.scale.z),
+                0.0,
+            );
+            *normal = vector::Vec3::from_vec4(rotation.multiply(&mut n)).normalize();
+        }
+
+        self.rotation = vector::Vec3::new(0.0, 0.0, 0.0);
+        self.scale = vector::Vec3::new(1.0, 1.0, 1.0);
+        self.translation = vector::Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    /// Load a mesh, picking the loader from the file extension
+    pub fn load(filename: &str) -> Result<Mesh, String> {
+        let extension = std::path::Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        if !std::path::Path::new(filename).is_file() {
+            return Err(format!("{}: file not found", filename));
+        }
+        match extension.as_deref() {
+            Some("obj") => Ok(Mesh::load_from_file(filename)),
+            Some("ply") => Mesh::load_from_ply(filename),
+            Some("gltf") | Some("glb") => gltf::load(filename).map(|scene| scene.into_mesh()),
+            _ => Err(format!("{}: unsupported mesh format", filename)),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn load_from_file(filename: &str) -> Mesh {
         let mut vertices: Vec<vector::Vec3> = Vec::new();
@@ -160,8 +222,7 @@ impl Mesh {
                 "f" => {
                     // f 1/1/1 5/2/1 4/3/1
                     let mut face: Face = Face::new(0, 0, 0, Color::RGBA(240, 240, 240, 255));
-                    let mut i = 0;
-                    for word in words {
+                    for (i, word) in words.enumerate() {
                         let mut indices = word.split('/');
                         let index: usize = indices.next().unwrap().parse().unwrap();
                         match i {
@@ -170,7 +231,6 @@ impl Mesh {
                             2 => face.c = index,
                             _ => {}
                         }
-                        i += 1;
                     }
                     faces.push(face);
                 }
@@ -178,12 +238,466 @@ impl Mesh {
             }
         }
 
-        Mesh {
-            vertices,
-            faces,
-            rotation: vector::Vec3::new(0.0, 0.0, 0.0),
-            scale: vector::Vec3::new(1.0, 1.0, 1.0),
-            translation: vector::Vec3::new(0.0, 0.0, 0.0),
+        Mesh::from_geometry(vertices, faces)
+    }
+    /// Load a PLY file (ascii, binary little endian or binary big endian)
+    pub fn load_from_ply(filename: &str) -> Result<Mesh, String> {
+        let bytes = std::fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Mesh::from_ply_bytes(&bytes).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    /// Read a mesh from the contents of a PLY file
+    pub fn from_ply_bytes(bytes: &[u8]) -> Result<Mesh, String> {
+        let (elements, mut reader) = ply_read_header(bytes)?;
+
+        let mut vertices: Vec<vector::Vec3> = Vec::new();
+        let mut vertex_colors: Vec<Color> = Vec::new();
+        let mut normals: Vec<vector::Vec3> = Vec::new();
+        let mut uvs: Vec<vector::Vec2> = Vec::new();
+        // faces are resolved once every element is read, they may come before the vertices
+        let mut polygons: Vec<(Vec<usize>, Option<Color>)> = Vec::new();
+
+        for element in &elements {
+            let has_property = |names: &[&str]| {
+                element.properties.iter().any(|property| match property {
+                    PlyProperty::Scalar { name, .. } => names.contains(&name.as_str()),
+                    PlyProperty::List { .. } => false,
+                })
+            };
+            let has_normal = has_property(&["nx", "ny", "nz"]);
+            let has_uv = has_property(&["u", "s", "texture_u", "v", "t", "texture_v"]);
+
+            for _ in 0..element.count {
+                let mut position = [0.0; 3];
+                let mut normal = [0.0; 3];
+                let mut uv = [0.0; 2];
+                let mut color = [0, 0, 0, 255];
+                let mut has_color = false;
+                let mut indices: Vec<usize> = Vec::new();
+
+                for property in &element.properties {
+                    match property {
+                        PlyProperty::Scalar { name, ty } => {
+                            let value = reader.read(*ty)?;
+                            match name.as_str() {
+                                "x" => position[0] = value as f32,
+                                "y" => position[1] = value as f32,
+                                "z" => position[2] = value as f32,
+                                "nx" => normal[0] = value as f32,
+                                "ny" => normal[1] = value as f32,
+                                "nz" => normal[2] = value as f32,
+                                "u" | "s" | "texture_u" => uv[0] = value as f32,
+                                "v" | "t" | "texture_v" => uv[1] = value as f32,
+                                _ => {
+                                    if let Some(channel) = ply_color_channel(name) {
+                                        color[channel] = ply_color_value(value, *ty);
+                                        has_color = true;
+                                    }
+                                }
+                            }
+                        }
+                        PlyProperty::List {
+                            name,
+                            count_ty,
+                            item_ty,
+                        } => {
+                            let count =
+                                ply_index(reader.read(*count_ty)?).ok_or("invalid list length")?;
+                            for _ in 0..count {
+                                let value = reader.read(*item_ty)?;
+                                if name == "vertex_indices" || name == "vertex_index" {
+                                    indices.push(
+                                        ply_index(value)
+                                            .ok_or(format!("invalid vertex index {}", value))?,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let color = Color::RGBA(color[0], color[1], color[2], color[3]);
+                match element.name.as_str() {
+                    "vertex" => {
+                        vertices.push(vector::Vec3::new(position[0], position[1], position[2]));
+                        if has_normal {
+                            normals.push(vector::Vec3::new(normal[0], normal[1], normal[2]));
+                        }
+                        if has_uv {
+                            uvs.push(vector::Vec2::new(uv[0], uv[1]));
+                        }
+                        if has_color {
+                            vertex_colors.push(color);
+                        }
+                    }
+                    "face" => polygons.push((indices, if has_color { Some(color) } else { None })),
+                    _ => {}
+                }
+            }
+        }
+
+        if !vertex_colors.is_empty() && vertex_colors.len() != vertices.len() {
+            return Err("not every vertex has a color".to_string());
+        }
+
+        let mut faces: Vec<Face> = Vec::new();
+        for (indices, face_color) in polygons {
+            if let Some(index) = indices.iter().find(|&&index| index >= vertices.len()) {
+                return Err(format!("face references missing vertex {}", index));
+            }
+            // triangulate polygons as a fan around the first vertex
+            for i in 1..indices.len().saturating_sub(1) {
+                let (a, b, c) = (indices[0], indices[i], indices[i + 1]);
+                let color = match face_color {
+                    Some(color) => color,
+                    // flat filled faces take the average, shaded mode interpolates them
+                    None if !vertex_colors.is_empty() => {
+                        average_color(&[vertex_colors[a], vertex_colors[b], vertex_colors[c]])
+                    }
+                    None => Color::RGBA(240, 240, 240, 255),
+                };
+                // face indices are 1-based like in obj files
+                faces.push(Face::new(a + 1, b + 1, c + 1, color));
+            }
+        }
+
+        let mut mesh = Mesh::from_geometry(vertices, faces);
+        mesh.vertex_colors = vertex_colors;
+        mesh.normals = normals;
+        mesh.uvs = uvs;
+        Ok(mesh)
+    }
+}
+
+pub fn average_color(colors: &[Color]) -> Color {
+    let n = colors.len() as u32;
+    let sum = |channel: fn(&Color) -> u8| colors.iter().map(|c| channel(c) as u32).sum::<u32>() / n;
+    Color::RGBA(
+        sum(|c| c.r) as u8,
+        sum(|c| c.g) as u8,
+        sum(|c| c.b) as u8,
+        sum(|c| c.a) as u8,
+    )
+}
+
+// PLY loading
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<PlyType, String> {
+        match name {
+            "char" | "int8" => Ok(PlyType::Int8),
+            "uchar" | "uint8" => Ok(PlyType::UInt8),
+            "short" | "int16" => Ok(PlyType::Int16),
+            "ushort" | "uint16" => Ok(PlyType::UInt16),
+            "int" | "int32" => Ok(PlyType::Int32),
+            "uint" | "uint32" => Ok(PlyType::UInt32),
+            "float" | "float32" => Ok(PlyType::Float32),
+            "double" | "float64" => Ok(PlyType::Float64),
+            _ => Err(format!("unknown property type '{}'", name)),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::Int8 | PlyType::UInt8 => 1,
+            PlyType::Int16 | PlyType::UInt16 => 2,
+            PlyType::Int32 | PlyType::UInt32 | PlyType::Float32 => 4,
+            PlyType::Float64 => 8,
+        }
+    }
+}
+
+enum PlyProperty {
+    Scalar {
+        name: String,
+        ty: PlyType,
+    },
+    List {
+        name: String,
+        count_ty: PlyType,
+        item_ty: PlyType,
+    },
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+enum PlyReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        body: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+impl<'a> PlyReader<'a> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, String> {
+        match self {
+            PlyReader::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of file")?;
+                token
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid value '{}'", token))
+            }
+            PlyReader::Binary {
+                body,
+                position,
+                big_endian,
+            } => {
+                let size = ty.size();
+                let bytes = body
+                    .get(*position..*position + size)
+                    .ok_or("unexpected end of file")?;
+                *position += size;
+
+                // normalize to little endian before decoding
+                let mut raw = [0u8; 8];
+                raw[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+
+                Ok(match ty {
+                    PlyType::Int8 => raw[0] as i8 as f64,
+                    PlyType::UInt8 => raw[0] as f64,
+                    PlyType::Int16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    PlyType::UInt16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    PlyType::Int32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::UInt32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::Float32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::Float64 => f64::from_le_bytes(raw),
+                })
+            }
+        }
+    }
+}
+
+fn ply_read_header(bytes: &[u8]) -> Result<(Vec<PlyElement>, PlyReader<'_>), String> {
+    const END_HEADER: &[u8] = b"end_header";
+
+    let header_end = bytes
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or("missing end_header")?;
+    // the body starts right after the end_header line
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |offset| header_end + offset + 1);
+    let header =
+        std::str::from_utf8(&bytes[..header_end]).map_err(|_| "header is not valid text")?;
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("not a ply file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _version] => format = Some(*name),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count '{}'", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, item_ty, name] => elements
+                .last_mut()
+                .ok_or("property declared before any element")?
+                .properties
+                .push(PlyProperty::List {
+                    name: name.to_string(),
+                    count_ty: PlyType::parse(count_ty)?,
+                    item_ty: PlyType::parse(item_ty)?,
+                }),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or("property declared before any element")?
+                .properties
+                .push(PlyProperty::Scalar {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)?,
+                }),
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(format!("invalid header line '{}'", line.trim())),
+        }
+    }
+
+    let body = &bytes[body_start..];
+    let reader = match format {
+        Some("ascii") => {
+            let text = std::str::from_utf8(body).map_err(|_| "ascii body is not valid text")?;
+            PlyReader::Ascii(text.split_ascii_whitespace())
+        }
+        Some("binary_little_endian") => PlyReader::Binary {
+            body,
+            position: 0,
+            big_endian: false,
+        },
+        Some("binary_big_endian") => PlyReader::Binary {
+            body,
+            position: 0,
+            big_endian: true,
+        },
+        Some(other) => return Err(format!("unknown format '{}'", other)),
+        None => return Err("missing format line".to_string()),
+    };
+    Ok((elements, reader))
+}
+
+// Counts and vertex indices are whole numbers from 0, whatever type they are stored as
+fn ply_index(value: f64) -> Option<usize> {
+    (value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as f64).then_some(value as usize)
+}
+
+fn ply_color_channel(name: &str) -> Option<usize> {
+    match name {
+        "red" | "r" | "diffuse_red" => Some(0),
+        "green" | "g" | "diffuse_green" => Some(1),
+        "blue" | "b" | "diffuse_blue" => Some(2),
+        "alpha" | "a" => Some(3),
+        _ => None,
+    }
+}
+
+// integer channels are 0-255, float channels are 0-1
+fn ply_color_value(value: f64, ty: PlyType) -> u8 {
+    match ty {
+        PlyType::Float32 | PlyType::Float64 => (value * 255.0).round().clamp(0.0, 255.0) as u8,
+        _ => value.clamp(0.0, 255.0) as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit square in the z = 0 plane as one quad face
+    const SQUARE: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+
+    fn corners(mesh: &Mesh) -> Vec<(usize, usize, usize)> {
+        mesh.faces
+            .iter()
+            .map(|face| (face.a, face.b, face.c))
+            .collect()
+    }
+
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        mesh.vertices.iter().map(|v| [v.x, v.y, v.z]).collect()
+    }
+
+    // Header and body of the square in a binary format, with int32 indices
+    fn binary_square(
+        format: &str,
+        to_bytes: fn(f32) -> [u8; 4],
+        index: fn(i32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut bytes = format!(
+            "ply\nformat {} 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+             property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format
+        )
+        .into_bytes();
+        for vertex in SQUARE {
+            for value in vertex {
+                bytes.extend(to_bytes(value));
+            }
+        }
+        bytes.push(4);
+        for i in 0..4 {
+            bytes.extend(index(i));
+        }
+        bytes
+    }
+
+    #[test]
+    fn ascii_ply() {
+        let ply = "ply\nformat ascii 1.0\ncomment a square\nelement vertex 4\n\
+                   property float x\nproperty float y\nproperty float z\n\
+                   element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                   0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        let mesh = Mesh::from_ply_bytes(ply.as_bytes()).unwrap();
+        assert_eq!(positions(&mesh), SQUARE);
+        // fan around the first corner, 1-based
+        assert_eq!(corners(&mesh), [(1, 2, 3), (1, 3, 4)]);
+    }
+
+    #[test]
+    fn binary_ply_in_both_byte_orders() {
+        let little = binary_square("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        let big = binary_square("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        for bytes in [little, big] {
+            let mesh = Mesh::from_ply_bytes(&bytes).unwrap();
+            assert_eq!(positions(&mesh), SQUARE);
+            assert_eq!(corners(&mesh), [(1, 2, 3), (1, 3, 4)]);
+        }
+    }
+
+    #[test]
+    fn ply_properties_in_any_order() {
+        // faces before vertices, colors and normals mixed in with the positions
+        let ply = "ply\nformat ascii 1.0\nelement face 1\n\
+                   property list uchar int vertex_index\nelement vertex 3\n\
+                   property uchar red\nproperty float z\nproperty float nx\nproperty float x\n\
+                   property uchar green\nproperty float ny\nproperty float y\nproperty uchar blue\n\
+                   property float nz\nend_header\n3 2 1 0\n\
+                   255 3 0 1 0 0 2 0 1\n0 6 0 4 255 0 5 0 1\n0 9 0 7 0 0 8 255 1\n";
+        let mesh = Mesh::from_ply_bytes(ply.as_bytes()).unwrap();
+        assert_eq!(
+            positions(&mesh),
+            [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]
+        );
+        assert_eq!(corners(&mesh), [(3, 2, 1)]);
+        assert!(mesh
+            .normals
+            .iter()
+            .all(|n| (n.x, n.y, n.z) == (0.0, 0.0, 1.0)));
+        assert_eq!(
+            mesh.vertex_colors,
+            [
+                Color::RGB(255, 0, 0),
+                Color::RGB(0, 255, 0),
+                Color::RGB(0, 0, 255)
+            ]
+        );
+    }
+
+    #[test]
+    fn ply_rejects_invalid_indices() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                      property float y\nproperty float z\nelement face 1\n\
+                      property list uchar float vertex_indices\nend_header\n\
+                      0 0 0\n1 0 0\n1 1 0\n";
+        for face in ["3 0 1 -1", "3 0 1 1.5", "3 0 1 3", "-1 0 1 2"] {
+            let ply = format!("{}{}\n", header, face);
+            assert!(Mesh::from_ply_bytes(ply.as_bytes()).is_err(), "{}", face);
         }
+        let ply = format!("{}3 0 1 2\n", header);
+        assert!(Mesh::from_ply_bytes(ply.as_bytes()).is_ok());
     }
 }
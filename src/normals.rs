@@ -0,0 +1,146 @@
+// Per-vertex normal generation
+use serde::Deserialize;
+
+use crate::mesh::Mesh;
+use crate::vector::Vec3;
+
+// How much each face contributes to the normal of the vertices it touches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalWeighting {
+    Area,  // bigger faces pull harder
+    Angle, // faces pull by their angle at the vertex, independent of tessellation
+}
+
+impl Mesh {
+    /// Replace the normals with smooth per-vertex normals. Faces meeting at a vertex are
+    /// only averaged together when their normals are less than `crease_angle` (radians)
+    /// apart, vertices on sharper edges are split so each side keeps its own normal.
+    pub fn compute_normals(&mut self, weighting: NormalWeighting, crease_angle: f32) {
+        let cos_crease = crease_angle.cos();
+        let vertex_count = self.vertices.len();
+
+        // unit normal of every face and the weight of each of its corners
+        let mut face_normals: Vec<Vec3> = Vec::with_capacity(self.faces.len());
+        let mut corner_weights: Vec<[f32; 3]> = Vec::with_capacity(self.faces.len());
+        let mut incident_faces: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+
+        for (f, face) in self.faces.iter().enumerate() {
+            let corners = [face.a - 1, face.b - 1, face.c - 1];
+            let points = corners.map(|index| self.vertices[index]);
+            let cross = (points[1] - points[0]).cross(points[2] - points[0]);
+            let area = cross.len();
+
+            if area <= f32::EPSILON {
+                // degenerate faces don't contribute
+                face_normals.push(Vec3::new(0.0, 0.0, 0.0));
+                corner_weights.push([0.0; 3]);
+            } else {
+                face_normals.push(cross / area);
+                corner_weights.push(match weighting {
+                    NormalWeighting::Area => [area; 3],
+                    NormalWeighting::Angle => [0, 1, 2].map(|k| {
+                        let edge_1 = points[(k + 1) % 3] - points[k];
+                        let edge_2 = points[(k + 2) % 3] - points[k];
+                        let cos = edge_1.dot(edge_2) / (edge_1.len() * edge_2.len());
+                        cos.clamp(-1.0, 1.0).acos()
+                    }),
+                });
+            }
+            for index in corners {
+                if incident_faces[index].last() != Some(&f) {
+                    incident_faces[index].push(f);
+                }
+            }
+        }
+
+        let mut vertices = self.vertices.clone();
+        let mut normals: Vec<Vec3> = vec![Vec3::new(0.0, 0.0, 0.0); vertex_count];
+        let mut faces = self.faces.clone();
+
+        for (vertex, around) in incident_faces.iter().enumerate() {
+            // faces around the vertex join the same group when they share an edge that
+            // is not sharper than the crease angle, each group gets its own vertex
+            let mut group: Vec<usize> = (0..around.len()).collect();
+            for i in 0..around.len() {
+                for j in i + 1..around.len() {
+                    let (f, g) = (around[i], around[j]);
+                    let smooth = face_normals[f].dot(face_normals[g]) >= cos_crease
+                        || corner_weights[f] == [0.0; 3]
+                        || corner_weights[g] == [0.0; 3];
+                    if smooth && self.share_edge(f, g, vertex) {
+                        let (root_i, root_j) = (find_root(&mut group, i), find_root(&mut group, j));
+                        group[root_i] = root_j;
+                    }
+                }
+            }
+
+            let mut group_vertices: Vec<(usize, usize)> = Vec::new();
+            for (i, &f) in around.iter().enumerate() {
+                let root = find_root(&mut group, i);
+                let target = match group_vertices.iter().find(|(r, _)| *r == root) {
+                    Some(&(_, target)) => target,
+                    None => {
+                        // the first group keeps the original vertex
+                        let target = if group_vertices.is_empty() {
+                            vertex
+                        } else {
+                            vertices.push(self.vertices[vertex]);
+                            normals.push(Vec3::new(0.0, 0.0, 0.0));
+                            if !self.vertex_colors.is_empty() {
+                                self.vertex_colors.push(self.vertex_colors[vertex]);
+                            }
+                            if !self.uvs.is_empty() {
+                                self.uvs.push(self.uvs[vertex]);
+                            }
+                            vertices.len() - 1
+                        };
+                        group_vertices.push((root, target));
+                        target
+                    }
+                };
+
+                normals[target] = normals[target]
+                    + face_normals[f] * corner_weight(self, &corner_weights, f, vertex);
+                let face = &mut faces[f];
+                for corner in [&mut face.a, &mut face.b, &mut face.c] {
+                    if *corner == vertex + 1 {
+                        *corner = target + 1;
+                    }
+                }
+            }
+        }
+
+        for normal in normals.iter_mut() {
+            if normal.len() > f32::EPSILON {
+                *normal = normal.normalize();
+            }
+        }
+        self.vertices = vertices;
+        self.normals = normals;
+        self.faces = faces;
+    }
+
+    fn share_edge(&self, f: usize, g: usize, vertex: usize) -> bool {
+        let (f, g) = (&self.faces[f], &self.faces[g]);
+        [f.a, f.b, f.c]
+            .iter()
+            .any(|&index| index != vertex + 1 && [g.a, g.b, g.c].contains(&index))
+    }
+}
+
+fn find_root(group: &mut [usize], mut i: usize) -> usize {
+    while group[i] != i {
+        group[i] = group[group[i]];
+        i = group[i];
+    }
+    i
+}
+
+fn corner_weight(mesh: &Mesh, corner_weights: &[[f32; 3]], face: usize, vertex: usize) -> f32 {
+    let face_corners = [mesh.faces[face].a, mesh.faces[face].b, mesh.faces[face].c];
+    face_corners
+        .iter()
+        .position(|&index| index - 1 == vertex)
+        .map_or(0.0, |k| corner_weights[face][k])
+}
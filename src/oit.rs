@@ -0,0 +1,145 @@
+// Weighted blended order-independent transparency (McGuire and Bavoil, 2013). The
+// transparent fragments of a frame are summed into an accumulation buffer, weighted
+// so the nearer ones count more, and the product of their transparencies is kept in a
+// revealage buffer. Sums and products don't depend on the order the fragments come
+// in, so intersecting and cyclically overlapping faces need no sorting. The result is
+// an approximation: it matches sorted blending exactly for one layer, and closely for
+// layers of similar colors.
+use sdl2::pixels::Color;
+
+use crate::display::ColorBuffer;
+
+pub struct WeightedBuffer {
+    accumulation: Vec<[f32; 4]>, // premultiplied color and alpha, times the weight
+    revealage: Vec<f32>,         // how much of the opaque pixel shows through
+    width: u32,
+}
+
+impl WeightedBuffer {
+    pub fn new(width: u32, height: u32) -> WeightedBuffer {
+        let size = (width * height) as usize;
+        WeightedBuffer {
+            accumulation: vec![[0.0; 4]; size],
+            revealage: vec![1.0; size],
+            width,
+        }
+    }
+
+    /// Nothing transparent drawn yet
+    pub fn clear(&mut self) {
+        self.accumulation.fill([0.0; 4]);
+        self.revealage.fill(1.0);
+    }
+
+    /// Add a fragment of `color`, with its alpha as opacity, at the pixel `x`, `y` and
+    /// `depth` between the near (0) and far (1) planes
+    pub fn accumulate(&mut self, x: u32, y: u32, color: Color, depth: f32) {
+        let index = (y * self.width + x) as usize;
+        let alpha = color.a as f32 / 255.0;
+        if alpha <= 0.0 {
+            return;
+        }
+        let weight = alpha * weight(depth);
+        let sum = &mut self.accumulation[index];
+        for (channel, value) in [color.r, color.g, color.b].into_iter().enumerate() {
+            sum[channel] += value as f32 / 255.0 * weight;
+        }
+        sum[3] += weight;
+        self.revealage[index] *= 1.0 - alpha;
+    }
+
+    /// Blend the weighted average of the transparent fragments of every pixel over the
+    /// color buffer, by how much of the pixel they cover
+    pub fn composite(&self, color_buffer: &mut ColorBuffer) {
+        let size = color_buffer.format.bytes_per_pixel();
+        let pixels = color_buffer.pixels.chunks_exact_mut(size);
+        for ((pixel, sum), &revealage) in pixels.zip(&self.accumulation).zip(&self.revealage) {
+            if revealage >= 1.0 {
+                continue;
+            }
+            let coverage = 1.0 - revealage;
+            for channel in 0..3 {
+                let average = sum[channel] / sum[3].max(1e-5);
+                let blended = average * 255.0 * coverage + pixel[channel] as f32 * revealage;
+                pixel[channel] = blended.round().clamp(0.0, 255.0) as u8;
+            }
+            if let Some(alpha) = pixel.get_mut(3) {
+                *alpha = (255.0 * coverage + *alpha as f32 * revealage).round() as u8;
+            }
+        }
+    }
+}
+
+// Nearer fragments weigh more, so they dominate the average like they would when
+// blended in order. Equation 10 of the paper, for depths between 0 and 1
+fn weight(depth: f32) -> f32 {
+    (3e3 * (1.0 - depth.clamp(0.0, 1.0)).powi(3)).max(1e-2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::PixelFormat;
+    use crate::render_state::BlendMode;
+
+    const BACKGROUND: [u8; 4] = [40, 200, 90, 60];
+
+    // The transparent fragments composited over a single pixel of the background
+    fn composite(format: PixelFormat, fragments: &[(Color, f32)]) -> Vec<u8> {
+        let mut color_buffer = ColorBuffer::new(1, 1, format);
+        let size = format.bytes_per_pixel();
+        color_buffer.pixels.copy_from_slice(&BACKGROUND[..size]);
+        let mut buffer = WeightedBuffer::new(1, 1);
+        for &(color, depth) in fragments {
+            buffer.accumulate(0, 0, color, depth);
+        }
+        buffer.composite(&mut color_buffer);
+        color_buffer.pixels
+    }
+
+    fn assert_close(a: &[u8], b: &[u8]) {
+        let close = a.iter().zip(b).all(|(&a, &b)| a.abs_diff(b) <= 1);
+        assert!(a.len() == b.len() && close, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn one_layer_matches_alpha_blending() {
+        for format in [PixelFormat::Rgb24, PixelFormat::Rgba32] {
+            let color = Color::RGBA(200, 30, 120, 100);
+            let mut expected = BACKGROUND[..format.bytes_per_pixel()].to_vec();
+            BlendMode::Alpha.apply(&mut expected, color, [true; 3]);
+            assert_close(&composite(format, &[(color, 0.3)]), &expected);
+        }
+    }
+
+    #[test]
+    fn order_does_not_matter() {
+        let near = (Color::RGBA(250, 10, 10, 150), 0.2);
+        let far = (Color::RGBA(10, 10, 250, 90), 0.7);
+        for format in [PixelFormat::Rgb24, PixelFormat::Rgba32] {
+            assert_eq!(
+                composite(format, &[near, far]),
+                composite(format, &[far, near])
+            );
+        }
+    }
+
+    #[test]
+    fn transparent_fragment_changes_nothing() {
+        let invisible = (Color::RGBA(255, 255, 255, 0), 0.5);
+        assert_eq!(composite(PixelFormat::Rgba32, &[invisible]), BACKGROUND);
+    }
+
+    #[test]
+    fn alpha_is_the_coverage_of_every_layer() {
+        let fragments = [
+            (Color::RGBA(250, 10, 10, 128), 0.2),
+            (Color::RGBA(10, 10, 250, 64), 0.6),
+        ];
+        // the background shows through both layers
+        let revealage = (1.0 - 128.0 / 255.0) * (1.0 - 64.0 / 255.0);
+        let alpha = 255.0 * (1.0 - revealage) + BACKGROUND[3] as f32 * revealage;
+        let pixel = composite(PixelFormat::Rgba32, &fragments);
+        assert_close(&pixel[3..], &[alpha.round() as u8]);
+    }
+}
@@ -0,0 +1,335 @@
+// Vertex and face stages of the pipeline. They only borrow what they need from the
+// renderer, so large meshes can run them on the rayon thread pool
+use std::ops::Range;
+
+use rayon::prelude::*;
+use sdl2::pixels::Color;
+
+use crate::display;
+use crate::light::{self, Light};
+use crate::matrix::Matrix;
+use crate::mesh::Mesh;
+use crate::render_state::{RenderState, Winding};
+use crate::shadow::Shadows;
+use crate::triangle::{Face, Triangle};
+use crate::vector::{Vec2, Vec3, Vec4};
+use crate::vertex_cache::{TransformedVertex, VertexCache};
+
+// Meshes with fewer faces are projected on the rendering thread, the thread pool
+// costs more than it saves on them
+pub const PARALLEL_FACES: usize = 8192;
+
+// Debug colors of the faces facing the camera and facing away from it
+pub const FRONT_FACE_COLOR: Color = Color::RGB(60, 140, 230);
+pub const BACK_FACE_COLOR: Color = Color::RGB(230, 70, 60);
+
+// Vertices moved to view space together, and handed to a thread together
+pub const VERTEX_BATCH: usize = 256;
+
+// Moves mesh vertices to view space and projects them to the screen
+pub struct VertexStage {
+    pub model_view: Matrix,
+    pub projection: Matrix,
+}
+
+impl VertexStage {
+    pub fn transform(&self, vertex: Vec3) -> TransformedVertex {
+        // the matrix is copied, multiplying needs it mutable
+        let mut model_view = self.model_view;
+
+        // Use a matrix to scale, rotate, and translate the mesh
+        self.project(model_view.multiply(&mut Vec4::from_vec3(vertex)))
+    }
+
+    /// `transform` every vertex into `out`, moving them to view space a batch at a time
+    pub fn transform_batch(&self, vertices: &[Vec3], out: &mut [Option<TransformedVertex>]) {
+        let mut batch = [Vec4::new(0.0, 0.0, 0.0, 0.0); VERTEX_BATCH];
+        for (vertices, out) in vertices
+            .chunks(VERTEX_BATCH)
+            .zip(out.chunks_mut(VERTEX_BATCH))
+        {
+            let batch = &mut batch[..vertices.len()];
+            for (view, vertex) in batch.iter_mut().zip(vertices) {
+                *view = Vec4::from_vec3(*vertex);
+            }
+            self.model_view.multiply_batch(batch);
+            for (out, view) in out.iter_mut().zip(batch.iter()) {
+                *out = Some(self.project(*view));
+            }
+        }
+    }
+
+    fn project(&self, view: Vec4) -> TransformedVertex {
+        let mut projection = self.projection;
+
+        // Projecting 3D points to 2D
+        let mut projected = projection.multiply_vec4_projection(&view);
+        // Scaling projected point, y is flipped as it grows down on the screen
+        projected.x *= display::WINDOW_WIDTH as f32 / 2.0;
+        projected.y *= -(display::WINDOW_HEIGHT as f32) / 2.0;
+
+        // Transforming projected point to screen space
+        projected.x += display::WINDOW_WIDTH as f32 / 2.0;
+        projected.y += display::WINDOW_HEIGHT as f32 / 2.0;
+        TransformedVertex {
+            view,
+            screen: Vec2::new(projected.x, projected.y),
+        }
+    }
+}
+
+// Lights, culls and assembles the triangle of a face from its transformed vertices
+pub struct FaceStage<'a> {
+    pub lights: &'a [Light], // in view space
+    pub ambient: f32,
+    pub shadows: &'a Shadows,
+    pub apply_light: bool,
+    pub state: RenderState,        // culls the faces, and blends the triangles
+    pub front_face: Winding,       // of the facing colors
+    pub facing_colors: bool,       // color front and back faces instead of their own color
+    pub base_color: Option<Color>, // material color replacing the face colors
+    pub opacity: f32,              // alpha of the triangles, from the material
+}
+
+impl FaceStage<'_> {
+    /// Triangle to draw for the face, None when it is culled
+    pub fn triangle(&self, face: &Face, vertices: [TransformedVertex; 3]) -> Option<Triangle> {
+        // twice the area of the triangle on the screen, positive when it is clockwise
+        let [a, b, c] = vertices.map(|vertex| vertex.screen);
+        let signed_area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+        // culled before lighting, which costs the most
+        if self.state.culls(signed_area) {
+            return None;
+        }
+
+        let vector_a = Vec3::from_vec4(vertices[0].view); //     A
+        let vector_b = Vec3::from_vec4(vertices[1].view); //   /   \
+        let vector_c = Vec3::from_vec4(vertices[2].view); //  C-----B
+
+        // Calculate Normal
+        let vector_ab = (vector_b - vector_a).normalize();
+        let vector_ac = (vector_c - vector_a).normalize();
+        let normal = vector_ab.cross(vector_ac).normalize();
+        let front = (signed_area > 0.0) == (self.front_face == Winding::Clockwise);
+
+        let face_color = match (self.facing_colors, front) {
+            (true, true) => FRONT_FACE_COLOR,
+            (true, false) => BACK_FACE_COLOR,
+            (false, _) => self.base_color.unwrap_or(face.color),
+        };
+        let mut light_color = face_color;
+        light_color.a = (self.opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        if self.apply_light {
+            // lit at the center of the face, clamped between 0 and 1 per channel
+            let center = (vector_a + vector_b + vector_c) / 3.0;
+            let light = light::illuminate(self.lights, self.ambient, self.shadows, center, normal);
+            light_color = light_apply_color(light, light_color);
+        }
+        // Calculating average depth of triangle
+        let avg_depth =
+            (vertices[0].view.z + vertices[1].view.z + vertices[2].view.z) / vertices.len() as f32;
+
+        Some(Triangle {
+            points: [vertices[0].screen, vertices[1].screen, vertices[2].screen],
+            color: light_color,
+            avg_depth,
+            blend: self.state.blend,
+        })
+    }
+}
+
+/// Append the triangles of the faces in `chunks` to `triangles`, in the order of the
+/// faces. Meshes of `PARALLEL_FACES` faces or more are projected on the thread pool.
+pub fn project_faces(
+    mesh: &Mesh,
+    chunks: impl Iterator<Item = Range<usize>>,
+    vertex_stage: &VertexStage,
+    face_stage: &FaceStage,
+    cache: &mut VertexCache,
+    triangles: &mut Vec<Triangle>,
+) {
+    if mesh.faces.len() < PARALLEL_FACES {
+        project_faces_serial(mesh, chunks, vertex_stage, face_stage, cache, triangles);
+    } else {
+        project_faces_parallel(mesh, chunks, vertex_stage, face_stage, cache, triangles);
+    }
+}
+
+fn project_faces_serial(
+    mesh: &Mesh,
+    chunks: impl Iterator<Item = Range<usize>>,
+    vertex_stage: &VertexStage,
+    face_stage: &FaceStage,
+    cache: &mut VertexCache,
+    triangles: &mut Vec<Triangle>,
+) {
+    // Transform and project every vertex once, the faces sharing it reuse it
+    cache.reset(mesh.vertices.len());
+    let mut transform_vertex = |index: usize| {
+        cache.get_or_insert_with(index, || vertex_stage.transform(mesh.vertices[index]))
+    };
+    for i in chunks.flatten() {
+        let face = &mesh.faces[i];
+        let vertices = [
+            transform_vertex(face.a - 1),
+            transform_vertex(face.b - 1),
+            transform_vertex(face.c - 1),
+        ];
+        triangles.extend(face_stage.triangle(face, vertices));
+    }
+}
+
+// Transforms all the vertices, then assembles the triangles of every chunk, on the
+// thread pool. Chunks are collected in order, so the triangles come out in the same
+// order as on the rendering thread
+fn project_faces_parallel(
+    mesh: &Mesh,
+    chunks: impl Iterator<Item = Range<usize>>,
+    vertex_stage: &VertexStage,
+    face_stage: &FaceStage,
+    cache: &mut VertexCache,
+    triangles: &mut Vec<Triangle>,
+) {
+    let chunks: Vec<_> = chunks.collect();
+    cache.transform_all(&mesh.vertices, vertex_stage);
+    let cache = &*cache;
+    let chunk_triangles: Vec<Vec<Triangle>> = chunks
+        .into_par_iter()
+        .map(|faces| {
+            faces
+                .filter_map(|i| {
+                    let face = &mesh.faces[i];
+                    let vertices = [
+                        cache.get(face.a - 1),
+                        cache.get(face.b - 1),
+                        cache.get(face.c - 1),
+                    ];
+                    face_stage.triangle(face, vertices)
+                })
+                .collect()
+        })
+        .collect();
+    for chunk in chunk_triangles {
+        triangles.extend(chunk);
+    }
+}
+
+/// `color` lit by `light`, the fraction of each channel that reaches the eye
+pub fn light_apply_color(light: Vec3, color: Color) -> Color {
+    let r = (color.r as f32 * light.x) as u8;
+    let g = (color.g as f32 * light.y) as u8;
+    let b = (color.b as f32 * light.z) as u8;
+    Color::RGBA(r, g, b, color.a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_state::{BlendMode, CullMode};
+    use crate::shadow::ShadowSettings;
+
+    fn vertex_stage() -> VertexStage {
+        VertexStage {
+            model_view: Matrix::world(
+                Vec3::new(1.0, 1.0, 1.0),
+                Vec3::new(0.3, 0.6, 0.1),
+                Vec3::new(0.2, -0.1, 4.0),
+            ),
+            projection: Matrix::make_perspetive(1.0, 0.75, 0.1, 100.0),
+        }
+    }
+
+    fn face_stage<'a>(lights: &'a [Light], shadows: &'a Shadows) -> FaceStage<'a> {
+        FaceStage {
+            lights,
+            ambient: 0.2,
+            shadows,
+            apply_light: true,
+            state: RenderState {
+                cull_mode: CullMode::Back,
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+            front_face: Winding::Clockwise,
+            facing_colors: false,
+            base_color: None,
+            opacity: 1.0,
+        }
+    }
+
+    #[test]
+    fn cached_projection_matches_per_face_transform() {
+        let mesh = Mesh::new_uv_sphere(1.0, 32, 16);
+        let vertex_stage = vertex_stage();
+        let lights = [Light::directional(Vec3::new(0.5, -1.0, 1.0), 1.0)];
+        let shadows = Shadows::new(ShadowSettings::default());
+        let face_stage = face_stage(&lights, &shadows);
+
+        // every corner transformed again, without the cache
+        let naive: Vec<Triangle> = mesh
+            .faces
+            .iter()
+            .filter_map(|face| {
+                let vertices =
+                    [face.a, face.b, face.c].map(|i| vertex_stage.transform(mesh.vertices[i - 1]));
+                face_stage.triangle(face, vertices)
+            })
+            .collect();
+
+        let mut cache = VertexCache::new();
+        let mut cached = Vec::new();
+        let chunks = mesh.face_chunks(100);
+        project_faces_serial(
+            &mesh,
+            chunks.iter().map(|chunk| chunk.faces.clone()),
+            &vertex_stage,
+            &face_stage,
+            &mut cache,
+            &mut cached,
+        );
+        assert!(!naive.is_empty());
+        assert_eq!(naive, cached);
+    }
+
+    #[test]
+    fn parallel_projection_matches_serial() {
+        let mesh = Mesh::new_uv_sphere(1.0, 128, 64);
+        assert!(mesh.faces.len() >= PARALLEL_FACES);
+        let chunks = mesh.face_chunks(1000);
+
+        let vertex_stage = vertex_stage();
+        let lights = [Light::directional(Vec3::new(0.5, -1.0, 1.0), 1.0)];
+        let shadows = Shadows::new(ShadowSettings::default());
+        let face_stage = face_stage(&lights, &shadows);
+        let project = |parallel: bool| {
+            let mut cache = VertexCache::new();
+            let mut triangles = Vec::new();
+            let chunks = chunks.iter().map(|chunk| chunk.faces.clone());
+            if parallel {
+                project_faces_parallel(
+                    &mesh,
+                    chunks,
+                    &vertex_stage,
+                    &face_stage,
+                    &mut cache,
+                    &mut triangles,
+                );
+            } else {
+                project_faces_serial(
+                    &mesh,
+                    chunks,
+                    &vertex_stage,
+                    &face_stage,
+                    &mut cache,
+                    &mut triangles,
+                );
+            }
+            triangles
+        };
+
+        let serial = project(false);
+        assert!(!serial.is_empty());
+        assert_eq!(serial, project(true));
+    }
+}
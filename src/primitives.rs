@@ -0,0 +1,375 @@
+// Procedural primitive meshes with outward winding, normals and UVs
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use sdl2::pixels::Color;
+
+use crate::mesh::Mesh;
+use crate::triangle::Face;
+use crate::vector::{Vec2, Vec3};
+
+pub const PRIMITIVE_NAMES: [&str; 8] = [
+    "cube",
+    "sphere",
+    "icosphere",
+    "cylinder",
+    "cone",
+    "torus",
+    "plane",
+    "capsule",
+];
+
+const PRIMITIVE_COLOR: Color = Color::RGBA(240, 240, 240, 255);
+
+// A point of the profile that gets revolved around the y axis
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    // normal in the (radial, y) plane
+    normal: (f32, f32),
+    v: f32,
+}
+
+struct Builder {
+    mesh: Mesh,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            mesh: Mesh::from_geometry(Vec::new(), Vec::new()),
+        }
+    }
+
+    // returns the 1-based index used by faces
+    fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> usize {
+        self.mesh.vertices.push(position);
+        self.mesh.normals.push(normal.normalize());
+        self.mesh.uvs.push(uv);
+        self.mesh.vertices.len()
+    }
+
+    // Orients the triangle so (b - a) x (c - a) follows the vertex normals, which
+    // is the outward winding the renderer expects. Zero area triangles are dropped.
+    fn triangle(&mut self, a: usize, b: usize, c: usize) {
+        let vertex = |index: usize| self.mesh.vertices[index - 1];
+        let normal = |index: usize| self.mesh.normals[index - 1];
+        let face_normal = (vertex(b) - vertex(a)).cross(vertex(c) - vertex(a));
+        if face_normal.len() <= f32::EPSILON {
+            return;
+        }
+        let face = if face_normal.dot(normal(a) + normal(b) + normal(c)) < 0.0 {
+            Face::new(a, c, b, PRIMITIVE_COLOR)
+        } else {
+            Face::new(a, b, c, PRIMITIVE_COLOR)
+        };
+        self.mesh.faces.push(face);
+    }
+
+    // a, b, c, d go around the quad
+    fn quad(&mut self, a: usize, b: usize, c: usize, d: usize) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    fn revolve(&mut self, profile: &[ProfilePoint], segments: usize) {
+        let segments = segments.max(3);
+        let mut rows: Vec<usize> = Vec::new();
+
+        for point in profile {
+            rows.push(self.mesh.vertices.len() + 1);
+            // the seam column is duplicated so it can have u = 1
+            for s in 0..=segments {
+                let u = s as f32 / segments as f32;
+                let (sin, cos) = (2.0 * PI * u).sin_cos();
+                self.vertex(
+                    Vec3::new(point.radius * cos, point.y, point.radius * sin),
+                    Vec3::new(point.normal.0 * cos, point.normal.1, point.normal.0 * sin),
+                    Vec2::new(u, point.v),
+                );
+            }
+        }
+
+        for pair in rows.windows(2) {
+            for s in 0..segments {
+                let (top, bottom) = (pair[0] + s, pair[1] + s);
+                self.quad(top, top + 1, bottom + 1, bottom);
+            }
+        }
+    }
+
+    // flat disk facing up or down, used to cap cylinders and cones
+    fn disk(&mut self, radius: f32, y: f32, facing_up: bool, segments: usize) {
+        let segments = segments.max(3);
+        let normal = Vec3::new(0.0, if facing_up { 1.0 } else { -1.0 }, 0.0);
+        let center = self.vertex(Vec3::new(0.0, y, 0.0), normal, Vec2::new(0.5, 0.5));
+        for s in 0..=segments {
+            let (sin, cos) = (2.0 * PI * s as f32 / segments as f32).sin_cos();
+            self.vertex(
+                Vec3::new(radius * cos, y, radius * sin),
+                normal,
+                Vec2::new(0.5 + 0.5 * cos, 0.5 + 0.5 * sin),
+            );
+        }
+        for s in 0..segments {
+            self.triangle(center, center + 1 + s, center + 2 + s);
+        }
+    }
+}
+
+impl Mesh {
+    /// Built-in primitive with default parameters, by name
+    pub fn new_primitive(name: &str) -> Result<Mesh, String> {
+        match name {
+            "cube" => Ok(Mesh::new_cube()),
+            "sphere" => Ok(Mesh::new_uv_sphere(1.0, 32, 16)),
+            "icosphere" => Ok(Mesh::new_icosphere(1.0, 2)),
+            "cylinder" => Ok(Mesh::new_cylinder(1.0, 2.0, 32)),
+            "cone" => Ok(Mesh::new_cone(1.0, 2.0, 32)),
+            "torus" => Ok(Mesh::new_torus(1.0, 0.35, 32, 16)),
+            "plane" => Ok(Mesh::new_plane(2.0, 2.0, 8, 8)),
+            "capsule" => Ok(Mesh::new_capsule(0.5, 1.0, 32, 8)),
+            _ => Err(format!(
+                "unknown primitive '{}', expected one of: {}",
+                name,
+                PRIMITIVE_NAMES.join(", ")
+            )),
+        }
+    }
+
+    /// Sphere made of `segments` meridians and `rings` parallels
+    pub fn new_uv_sphere(radius: f32, segments: usize, rings: usize) -> Mesh {
+        let rings = rings.max(2);
+        let profile: Vec<ProfilePoint> = (0..=rings)
+            .map(|r| {
+                let v = r as f32 / rings as f32;
+                let (sin, cos) = (PI * v).sin_cos();
+                ProfilePoint {
+                    radius: radius * sin,
+                    y: radius * cos,
+                    normal: (sin, cos),
+                    v,
+                }
+            })
+            .collect();
+
+        let mut builder = Builder::new();
+        builder.revolve(&profile, segments);
+        builder.mesh
+    }
+
+    /// Sphere made by subdividing an icosahedron, every triangle has about the same size
+    pub fn new_icosphere(radius: f32, subdivisions: usize) -> Mesh {
+        let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let mut points: Vec<Vec3> = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+        .collect();
+        let mut triangles: Vec<[usize; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, points: &mut Vec<Vec3>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    points.push(((points[a] + points[b]) * 0.5).normalize());
+                    points.len() - 1
+                })
+            };
+            let mut subdivided = Vec::with_capacity(triangles.len() * 4);
+            for [a, b, c] in triangles {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                subdivided.extend([[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+            }
+            triangles = subdivided;
+        }
+
+        let mut builder = Builder::new();
+        for point in &points {
+            // spherical mapping, the seam is not split
+            let uv = Vec2::new(
+                0.5 + point.z.atan2(point.x) / (2.0 * PI),
+                point.y.clamp(-1.0, 1.0).acos() / PI,
+            );
+            builder.vertex(*point * radius, *point, uv);
+        }
+        for [a, b, c] in triangles {
+            builder.triangle(a + 1, b + 1, c + 1);
+        }
+        builder.mesh
+    }
+
+    /// Capped cylinder centered on the origin along the y axis
+    pub fn new_cylinder(radius: f32, height: f32, segments: usize) -> Mesh {
+        let half = height / 2.0;
+        let mut builder = Builder::new();
+        builder.revolve(
+            &[
+                ProfilePoint {
+                    radius,
+                    y: half,
+                    normal: (1.0, 0.0),
+                    v: 0.0,
+                },
+                ProfilePoint {
+                    radius,
+                    y: -half,
+                    normal: (1.0, 0.0),
+                    v: 1.0,
+                },
+            ],
+            segments,
+        );
+        builder.disk(radius, half, true, segments);
+        builder.disk(radius, -half, false, segments);
+        builder.mesh
+    }
+
+    /// Cone with its apex up the y axis and a capped base, centered on the origin
+    pub fn new_cone(radius: f32, height: f32, segments: usize) -> Mesh {
+        let half = height / 2.0;
+        // the side normal leans up by the slope of the cone
+        let slant = (height * height + radius * radius).sqrt();
+        let normal = (height / slant, radius / slant);
+
+        let mut builder = Builder::new();
+        builder.revolve(
+            &[
+                ProfilePoint {
+                    radius: 0.0,
+                    y: half,
+                    normal,
+                    v: 0.0,
+                },
+                ProfilePoint {
+                    radius,
+                    y: -half,
+                    normal,
+                    v: 1.0,
+                },
+            ],
+            segments,
+        );
+        builder.disk(radius, -half, false, segments);
+        builder.mesh
+    }
+
+    /// Torus lying on the xz plane
+    pub fn new_torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: usize,
+        minor_segments: usize,
+    ) -> Mesh {
+        let minor_segments = minor_segments.max(3);
+        let profile: Vec<ProfilePoint> = (0..=minor_segments)
+            .map(|s| {
+                let v = s as f32 / minor_segments as f32;
+                let (sin, cos) = (2.0 * PI * v).sin_cos();
+                ProfilePoint {
+                    radius: major_radius + minor_radius * cos,
+                    y: minor_radius * sin,
+                    normal: (cos, sin),
+                    v,
+                }
+            })
+            .collect();
+
+        let mut builder = Builder::new();
+        builder.revolve(&profile, major_segments);
+        builder.mesh
+    }
+
+    /// Grid on the xz plane facing up, centered on the origin
+    pub fn new_plane(width: f32, depth: f32, x_divisions: usize, z_divisions: usize) -> Mesh {
+        let (x_divisions, z_divisions) = (x_divisions.max(1), z_divisions.max(1));
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+
+        let mut builder = Builder::new();
+        for z in 0..=z_divisions {
+            for x in 0..=x_divisions {
+                let u = x as f32 / x_divisions as f32;
+                let v = z as f32 / z_divisions as f32;
+                builder.vertex(
+                    Vec3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth),
+                    normal,
+                    Vec2::new(u, v),
+                );
+            }
+        }
+
+        let columns = x_divisions + 1;
+        for z in 0..z_divisions {
+            for x in 0..x_divisions {
+                let a = z * columns + x + 1;
+                let b = a + columns;
+                builder.quad(a, a + 1, b + 1, b);
+            }
+        }
+        builder.mesh
+    }
+
+    /// Cylinder of the given height with hemispheres on both ends, along the y axis
+    pub fn new_capsule(radius: f32, height: f32, segments: usize, rings: usize) -> Mesh {
+        let rings = rings.max(1);
+        let half = height / 2.0;
+        // v follows the length of the profile so the texture is not stretched
+        let length = PI * radius + height;
+
+        let mut profile: Vec<ProfilePoint> = Vec::new();
+        for (offset, start, v_start) in [
+            (half, 0.0, 0.0),
+            (-half, PI / 2.0, 0.5 * PI * radius + height),
+        ] {
+            for r in 0..=rings {
+                let angle = (PI / 2.0) * r as f32 / rings as f32;
+                let (sin, cos) = (start + angle).sin_cos();
+                profile.push(ProfilePoint {
+                    radius: radius * sin,
+                    y: offset + radius * cos,
+                    normal: (sin, cos),
+                    v: (v_start + angle * radius) / length,
+                });
+            }
+        }
+
+        let mut builder = Builder::new();
+        builder.revolve(&profile, segments);
+        builder.mesh
+    }
+}
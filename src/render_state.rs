@@ -0,0 +1,160 @@
+// Fixed-function settings of a draw call: which faces are drawn, how they are filled
+// and how their pixels are depth tested and written
+use sdl2::pixels::Color;
+use serde::Deserialize;
+
+// Faces skipped by their winding on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+// Winding of the front faces on the screen, the meshes are clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+// When a pixel passes the depth test, comparing its depth to the depth buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthFunc {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+    NotEqual,
+    Always,
+}
+
+impl DepthFunc {
+    pub fn passes(&self, depth: f32, stored: f32) -> bool {
+        match self {
+            DepthFunc::Never => false,
+            DepthFunc::Less => depth < stored,
+            DepthFunc::LessEqual => depth <= stored,
+            DepthFunc::Equal => depth == stored,
+            DepthFunc::GreaterEqual => depth >= stored,
+            DepthFunc::Greater => depth > stored,
+            DepthFunc::NotEqual => depth != stored,
+            DepthFunc::Always => true,
+        }
+    }
+}
+
+// How a pixel color is combined with the color already in the buffer, using the alpha
+// of the pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    Replace,  // the pixel color as is
+    Alpha,    // mix by alpha
+    Additive, // add the pixel color scaled by alpha
+    Multiply, // darken by the pixel color
+}
+
+impl BlendMode {
+    /// Channel of `source` blended over `destination`
+    pub fn blend(&self, source: u8, alpha: u8, destination: u8) -> u8 {
+        let (source, alpha, destination) = (source as u32, alpha as u32, destination as u32);
+        let blended = match self {
+            BlendMode::Replace => source,
+            BlendMode::Alpha => (source * alpha + destination * (255 - alpha)) / 255,
+            BlendMode::Additive => destination + source * alpha / 255,
+            BlendMode::Multiply => destination * source / 255,
+        };
+        blended.min(255) as u8
+    }
+
+    /// Blend `color` into an RGB24 or RGBA32 pixel, only writing the color channels
+    /// enabled in `mask`
+    pub fn apply(&self, pixel: &mut [u8], color: Color, mask: [bool; 3]) {
+        for (channel, source) in [color.r, color.g, color.b].into_iter().enumerate() {
+            if mask[channel] {
+                pixel[channel] = self.blend(source, color.a, pixel[channel]);
+            }
+        }
+        if let Some(alpha) = pixel.get_mut(3) {
+            // coverage of the pixel, additive and multiply only change its color
+            *alpha = match self {
+                BlendMode::Replace => color.a,
+                BlendMode::Alpha => self.blend(255, color.a, *alpha),
+                BlendMode::Additive | BlendMode::Multiply => *alpha,
+            };
+        }
+    }
+
+    /// Whether a color drawn with this mode lets what is behind it show through
+    pub fn is_transparent(&self, color: Color) -> bool {
+        match self {
+            BlendMode::Replace => false,
+            BlendMode::Alpha => color.a < 255,
+            BlendMode::Additive | BlendMode::Multiply => true,
+        }
+    }
+}
+
+// What is drawn of each face
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolygonMode {
+    Fill,
+    Line,  // edges only
+    Point, // corners only
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderState {
+    pub cull_mode: CullMode,
+    pub front_face: Winding,
+    pub depth_func: DepthFunc,
+    pub depth_write: bool,
+    pub blend: BlendMode,
+    pub polygon_mode: PolygonMode,
+    pub color_mask: [bool; 3], // red, green and blue channels written
+}
+
+impl Default for RenderState {
+    fn default() -> RenderState {
+        RenderState {
+            cull_mode: CullMode::None,
+            front_face: Winding::Clockwise,
+            depth_func: DepthFunc::Less,
+            depth_write: true,
+            blend: BlendMode::Replace,
+            polygon_mode: PolygonMode::Fill,
+            color_mask: [true; 3],
+        }
+    }
+}
+
+impl RenderState {
+    /// Whether a face with `signed_area` on the screen, positive when clockwise, is
+    /// culled
+    pub fn culls(&self, signed_area: f32) -> bool {
+        let front = self.is_front(signed_area);
+        match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Back => !front,
+            CullMode::Front => front,
+        }
+    }
+
+    /// Whether a face with `signed_area` on the screen is wound like the front faces
+    pub fn is_front(&self, signed_area: f32) -> bool {
+        (signed_area > 0.0) == (self.front_face == Winding::Clockwise)
+    }
+
+    /// Write `color` into the RGB24 or RGBA32 pixel, blended and masked
+    pub fn write_color(&self, pixel: &mut [u8], color: Color) {
+        self.blend.apply(pixel, color, self.color_mask);
+    }
+}
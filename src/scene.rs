@@ -0,0 +1,657 @@
+// Scene description files
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use sdl2::pixels::Color;
+use serde::Deserialize;
+
+use crate::animation::{Channel, Easing, Interpolation, Keyframe, Timeline, Track};
+use crate::display::{CullMethod, PixelFormat, RenderMethod, Transparency};
+use crate::gltf;
+use crate::graph::SceneGraph;
+use crate::instance::{MeshInstance, Model};
+use crate::light::{Light, LightKind};
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::normals::NormalWeighting;
+use crate::render_state::{BlendMode, RenderState, Winding};
+use crate::shadow::{self, ShadowSettings};
+use crate::vector::Vec3;
+
+pub struct Camera {
+    pub position: Vec3,
+    pub target: Vec3,
+    pub fov: f32, // vertical, in radians
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: Vec3::new(0.0, 0.0, 0.0),
+            target: Vec3::new(0.0, 0.0, 1.0),
+            fov: std::f32::consts::PI / 3.0,
+            near: 1.0,
+            far: 100.0,
+        }
+    }
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub graph: SceneGraph,
+    pub frame_instances: bool, // move the instances in front of the camera to fit the view
+    pub lights: Vec<Light>,
+    pub ambient: f32,                    // light reaching every surface, 0 to 1
+    pub shadows: Option<ShadowSettings>, // cast by directional and spot lights when set
+    pub instances: Vec<MeshInstance>,
+    pub timeline: Timeline,
+    pub render_method: RenderMethod,
+    pub cull_method: CullMethod,
+    pub front_face: Winding,
+    pub facing_colors: bool, // debug colors for front and back faces
+    pub transparency: Transparency,
+    pub apply_light: bool,
+    pub background: Color,
+    pub pixel_format: PixelFormat, // of the color buffer
+}
+
+impl Scene {
+    /// The instances framed by the default camera, lit from behind it
+    pub fn from_instances(instances: Vec<MeshInstance>) -> Scene {
+        Scene {
+            camera: Camera::default(),
+            graph: SceneGraph::new(),
+            frame_instances: true,
+            lights: vec![Light::directional(Vec3::new(0.0, 0.0, 1.0), 1.0)],
+            ambient: 0.0,
+            shadows: None,
+            instances,
+            timeline: Timeline::default(),
+            render_method: RenderMethod::Wireframe,
+            cull_method: CullMethod::None,
+            front_face: Winding::Clockwise,
+            facing_colors: false,
+            transparency: Transparency::Sorted,
+            apply_light: true,
+            background: Color::RGB(0, 0, 0),
+            pixel_format: PixelFormat::Rgb24,
+        }
+    }
+
+    /// Load a scene from a TOML file. Mesh paths are relative to the scene file and
+    /// meshes used more than once are only loaded once.
+    pub fn load(filename: &str) -> Result<Scene, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let document: DocScene =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", filename, e))?;
+        let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+        document
+            .into_scene(directory)
+            .map_err(|e| format!("{}: {}", filename, e))
+    }
+}
+
+// File layout, unknown keys are rejected so typos don't go unnoticed
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocScene {
+    camera: Option<DocCamera>,
+    lights: Option<Vec<DocLight>>,
+    ambient: Option<f32>,
+    shadows: Option<ShadowSettings>,
+    #[serde(default)]
+    nodes: Vec<DocNode>,
+    #[serde(default)]
+    meshes: Vec<DocMesh>,
+    animation: Option<DocAnimation>,
+    render_mode: Option<RenderMethod>,
+    cull_mode: Option<CullMethod>,
+    front_face: Option<Winding>,
+    facing_colors: Option<bool>,
+    transparency: Option<Transparency>,
+    lighting: Option<bool>,
+    background: Option<[u8; 3]>,
+    pixel_format: Option<PixelFormat>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocCamera {
+    position: Option<[f32; 3]>,
+    target: Option<[f32; 3]>,
+    fov: Option<f32>, // degrees
+    near: Option<f32>,
+    far: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocLight {
+    #[serde(rename = "type")]
+    kind: Option<LightKind>, // directional when missing
+    direction: Option<[f32; 3]>,
+    position: Option<[f32; 3]>,
+    color: Option<[u8; 3]>,
+    intensity: Option<f32>,
+    range: Option<f32>,
+    inner_cone: Option<f32>, // degrees
+    outer_cone: Option<f32>, // degrees
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocNode {
+    name: String,
+    parent: Option<String>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 3]>, // degrees
+    scale: Option<[f32; 3]>,
+    spin: Option<[f32; 3]>, // degrees per second
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocMesh {
+    path: Option<String>,
+    primitive: Option<String>,
+    node: Option<String>, // the transform is relative to this node
+    #[serde(default)]
+    hierarchy: bool, // keep the glTF nodes instead of merging them into one mesh
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 3]>, // degrees
+    scale: Option<[f32; 3]>,
+    spin: Option<[f32; 3]>, // degrees per second
+    color: Option<[u8; 3]>,
+    opacity: Option<f32>,     // 0 to 1
+    blend: Option<BlendMode>, // alpha when missing
+    visible: Option<bool>,
+    render_state: Option<RenderState>, // used when the scene is shaded
+    #[serde(default)]
+    normalize: bool,
+    crease_angle: Option<f32>,                 // degrees
+    normal_weighting: Option<NormalWeighting>, // angle when missing
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocAnimation {
+    speed: Option<f32>,
+    #[serde(rename = "loop")]
+    looping: Option<bool>,
+    autoplay: Option<bool>,
+    #[serde(default)]
+    tracks: Vec<DocTrack>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocTrack {
+    node: Option<String>,
+    property: Option<NodeProperty>, // only for nodes
+    camera: Option<CameraProperty>,
+    interpolation: Option<Interpolation>,
+    easing: Option<Easing>,
+    keys: Vec<DocKey>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocKey {
+    time: f32,       // seconds
+    value: [f32; 3], // rotations in degrees
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NodeProperty {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CameraProperty {
+    Position,
+    Target,
+}
+
+struct DocTransform {
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 3]>,
+    scale: Option<[f32; 3]>,
+    spin: Option<[f32; 3]>,
+}
+
+// Meshes already loaded by the scene, shared by every entry using them
+#[derive(Default)]
+struct Loaded {
+    models: HashMap<String, Rc<Model>>,
+    hierarchies: HashMap<String, (gltf::Scene, Vec<Rc<Model>>)>,
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn radians(v: [f32; 3]) -> Vec3 {
+    vec3(v.map(f32::to_radians))
+}
+
+impl DocScene {
+    fn into_scene(self, directory: &Path) -> Result<Scene, String> {
+        let mut scene = Scene::from_instances(Vec::new());
+        scene.frame_instances = false;
+
+        if let Some(camera) = self.camera {
+            let defaults = Camera::default();
+            scene.camera = Camera {
+                position: camera.position.map_or(defaults.position, vec3),
+                target: camera.target.map_or(defaults.target, vec3),
+                fov: camera.fov.map_or(defaults.fov, f32::to_radians),
+                near: camera.near.unwrap_or(defaults.near),
+                far: camera.far.unwrap_or(defaults.far),
+            };
+        }
+        let camera = &scene.camera;
+        if (camera.target - camera.position).len() == 0.0 {
+            return Err("camera target is the same as its position".to_string());
+        }
+        if !(camera.fov > 0.0 && camera.fov < std::f32::consts::PI) {
+            return Err("camera fov must be between 0 and 180 degrees".to_string());
+        }
+        if !(camera.near > 0.0 && camera.far > camera.near) {
+            return Err("camera needs 0 < near < far".to_string());
+        }
+
+        if let Some(lights) = self.lights {
+            scene.lights = lights
+                .into_iter()
+                .enumerate()
+                .map(|(i, light)| {
+                    light
+                        .into_light()
+                        .map_err(|e| format!("light {}: {}", i + 1, e))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        if let Some(ambient) = self.ambient {
+            if !(0.0..=1.0).contains(&ambient) {
+                return Err("ambient must be between 0 and 1".to_string());
+            }
+            scene.ambient = ambient;
+        }
+
+        if let Some(shadows) = self.shadows {
+            if !((1..=shadow::MAX_SIZE).contains(&shadows.size) && shadows.bias >= 0.0) {
+                return Err(format!(
+                    "shadows need a size from 1 to {} and a bias of at least 0",
+                    shadow::MAX_SIZE
+                ));
+            }
+            scene.shadows = Some(shadows);
+        }
+
+        for node in self.nodes {
+            node.add_to(&mut scene.graph)?;
+        }
+        let mut loaded = Loaded::default();
+        for (i, mesh) in self.meshes.into_iter().enumerate() {
+            let instances = mesh
+                .into_instances(directory, &mut scene.graph, &mut loaded)
+                .map_err(|e| format!("mesh {}: {}", i + 1, e))?;
+            scene.instances.extend(instances);
+        }
+
+        if let Some(animation) = self.animation {
+            scene.timeline = animation.into_timeline(&scene.graph)?;
+        }
+
+        if let Some(render_method) = self.render_mode {
+            scene.render_method = render_method;
+        }
+        if let Some(cull_method) = self.cull_mode {
+            scene.cull_method = cull_method;
+        }
+        if let Some(front_face) = self.front_face {
+            scene.front_face = front_face;
+        }
+        if let Some(facing_colors) = self.facing_colors {
+            scene.facing_colors = facing_colors;
+        }
+        if let Some(transparency) = self.transparency {
+            scene.transparency = transparency;
+        }
+        if let Some(lighting) = self.lighting {
+            scene.apply_light = lighting;
+        }
+        if let Some([r, g, b]) = self.background {
+            scene.background = Color::RGB(r, g, b);
+        }
+        if let Some(pixel_format) = self.pixel_format {
+            scene.pixel_format = pixel_format;
+        }
+        Ok(scene)
+    }
+}
+
+impl DocLight {
+    fn into_light(self) -> Result<Light, String> {
+        let kind = self.kind.unwrap_or(LightKind::Directional);
+        let direction = match (kind, self.direction) {
+            (LightKind::Point, _) => Vec3::new(0.0, 0.0, 1.0),
+            (_, Some(direction)) if vec3(direction).len() > 0.0 => vec3(direction),
+            (_, Some(_)) => return Err("direction can't be zero".to_string()),
+            (_, None) => return Err("needs a direction".to_string()),
+        };
+        let position = match (kind, self.position) {
+            (LightKind::Directional, Some(_)) => {
+                return Err("directional lights have no position".to_string())
+            }
+            (LightKind::Directional, None) => Vec3::new(0.0, 0.0, 0.0),
+            (_, Some(position)) => vec3(position),
+            (_, None) => return Err("needs a position".to_string()),
+        };
+        if self.range.is_some_and(|range| range <= 0.0) {
+            return Err("range must be positive".to_string());
+        }
+        // the glTF defaults
+        let inner_cone = self.inner_cone.map_or(0.0, f32::to_radians);
+        let outer_cone = self
+            .outer_cone
+            .map_or(std::f32::consts::FRAC_PI_4, f32::to_radians);
+        if !(0.0 <= inner_cone && inner_cone <= outer_cone && outer_cone <= std::f32::consts::PI) {
+            return Err("cones need 0 <= inner_cone <= outer_cone <= 180 degrees".to_string());
+        }
+
+        let intensity = self.intensity.unwrap_or(1.0);
+        let mut light = match kind {
+            LightKind::Directional => Light::directional(direction, intensity),
+            LightKind::Point => Light::point(position, intensity, self.range),
+            LightKind::Spot => Light::spot(
+                position, direction, intensity, self.range, inner_cone, outer_cone,
+            ),
+        };
+        let [r, g, b] = self.color.unwrap_or([255, 255, 255]);
+        light.color = Color::RGB(r, g, b);
+        Ok(light)
+    }
+}
+
+impl DocMesh {
+    fn transform(&self) -> DocTransform {
+        DocTransform {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale: self.scale,
+            spin: self.spin,
+        }
+    }
+
+    fn into_instances(
+        self,
+        directory: &Path,
+        graph: &mut SceneGraph,
+        loaded: &mut Loaded,
+    ) -> Result<Vec<MeshInstance>, String> {
+        let parent = match &self.node {
+            Some(name) => Some(graph.find(name).ok_or(format!("unknown node '{}'", name))?),
+            None => None,
+        };
+        let path = self
+            .path
+            .as_ref()
+            .map(|path| directory.join(path).to_string_lossy().into_owned());
+        let source = match (&path, &self.primitive) {
+            (Some(path), None) => path.clone(),
+            (None, Some(name)) => format!("primitive {}", name),
+            _ => return Err("needs either a path or a primitive".to_string()),
+        };
+
+        let mut instances = if self.hierarchy {
+            let lowercase = source.to_ascii_lowercase();
+            let is_gltf = lowercase.ends_with(".gltf") || lowercase.ends_with(".glb");
+            if path.is_none() || !is_gltf {
+                return Err("only .gltf and .glb files have a hierarchy".to_string());
+            }
+            if self.normalize {
+                return Err("normalize can't be used with hierarchy".to_string());
+            }
+            self.add_hierarchy(source, parent, graph, loaded)?
+        } else {
+            let mut instance = MeshInstance::new(self.load_model(source, &path, loaded)?);
+            instance.node = parent;
+            self.transform().apply(
+                &mut instance.translation,
+                &mut instance.rotation,
+                &mut instance.scale,
+                &mut instance.spin,
+            );
+            vec![instance]
+        };
+
+        for instance in instances.iter_mut() {
+            if let Some([r, g, b]) = self.color {
+                instance.material = Some(Material::new("scene", Color::RGB(r, g, b)));
+            }
+            if self.opacity.is_some() || self.blend.is_some() {
+                // the material of the mesh, made transparent for this instance
+                let mut material = instance
+                    .blend_material()
+                    .cloned()
+                    .ok_or("opacity and blend need a color or a mesh with a material")?;
+                if let Some(opacity) = self.opacity {
+                    if !(0.0..=1.0).contains(&opacity) {
+                        return Err(format!("opacity {} is not between 0 and 1", opacity));
+                    }
+                    material.opacity = opacity;
+                }
+                if let Some(blend) = self.blend {
+                    material.blend = blend;
+                }
+                instance.material = Some(material);
+            }
+            if let Some(visible) = self.visible {
+                instance.visible = visible;
+            }
+            instance.render_state = self.render_state;
+        }
+        Ok(instances)
+    }
+
+    // meshes loaded with the same options share their data
+    fn load_model(
+        &self,
+        source: String,
+        path: &Option<String>,
+        loaded: &mut Loaded,
+    ) -> Result<Rc<Model>, String> {
+        let key = format!(
+            "{}|{}|{:?}|{:?}",
+            source, self.normalize, self.crease_angle, self.normal_weighting
+        );
+        if let Some(model) = loaded.models.get(&key) {
+            return Ok(Rc::clone(model));
+        }
+        let mut mesh = match (path, &self.primitive) {
+            (Some(path), _) => Mesh::load(path)?,
+            (_, Some(name)) => Mesh::new_primitive(name)?,
+            _ => unreachable!(),
+        };
+        if self.normalize {
+            mesh.normalize_to_unit_box();
+        }
+        let model = Rc::new(self.prepare(mesh));
+        loaded.models.insert(key, Rc::clone(&model));
+        Ok(model)
+    }
+
+    // glTF nodes go under a new node that takes the transform of the entry
+    fn add_hierarchy(
+        &self,
+        path: String,
+        parent: Option<usize>,
+        graph: &mut SceneGraph,
+        loaded: &mut Loaded,
+    ) -> Result<Vec<MeshInstance>, String> {
+        let key = format!(
+            "{}|{:?}|{:?}",
+            path, self.crease_angle, self.normal_weighting
+        );
+        if !loaded.hierarchies.contains_key(&key) {
+            let mut gltf_scene = gltf::load(&path)?;
+            let models = std::mem::take(&mut gltf_scene.meshes)
+                .into_iter()
+                .map(|mesh| Rc::new(self.prepare(mesh)))
+                .collect();
+            loaded.hierarchies.insert(key.clone(), (gltf_scene, models));
+        }
+        let (gltf_scene, models) = &loaded.hierarchies[&key];
+
+        let name = Path::new(&path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let root = graph.add_node(&name, parent);
+        let node = &mut graph.nodes[root];
+        self.transform().apply(
+            &mut node.translation,
+            &mut node.rotation,
+            &mut node.scale,
+            &mut node.spin,
+        );
+        Ok(graph.add_gltf(gltf_scene, models, Some(root)))
+    }
+
+    fn prepare(&self, mut mesh: Mesh) -> Model {
+        if let Some(crease_angle) = self.crease_angle {
+            let weighting = self.normal_weighting.unwrap_or(NormalWeighting::Angle);
+            mesh.compute_normals(weighting, crease_angle.to_radians());
+        }
+        Model::new(mesh)
+    }
+}
+
+impl DocNode {
+    fn add_to(self, graph: &mut SceneGraph) -> Result<(), String> {
+        if graph.find(&self.name).is_some() {
+            return Err(format!("node '{}' is defined twice", self.name));
+        }
+        let parent = match &self.parent {
+            Some(parent) => Some(graph.find(parent).ok_or(format!(
+                "node '{}': parent '{}' must be defined before it",
+                self.name, parent
+            ))?),
+            None => None,
+        };
+        let id = graph.add_node(&self.name, parent);
+        let node = &mut graph.nodes[id];
+        let transform = DocTransform {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale: self.scale,
+            spin: self.spin,
+        };
+        transform.apply(
+            &mut node.translation,
+            &mut node.rotation,
+            &mut node.scale,
+            &mut node.spin,
+        );
+        Ok(())
+    }
+}
+
+impl DocAnimation {
+    fn into_timeline(self, graph: &SceneGraph) -> Result<Timeline, String> {
+        let mut timeline = Timeline::default();
+        if let Some(speed) = self.speed {
+            if !speed.is_finite() || speed < 0.0 {
+                return Err("animation speed must be finite and not negative".to_string());
+            }
+            timeline.speed = speed;
+        }
+        if let Some(looping) = self.looping {
+            timeline.looping = looping;
+        }
+        if let Some(autoplay) = self.autoplay {
+            timeline.playing = autoplay;
+        }
+        for (i, track) in self.tracks.into_iter().enumerate() {
+            let track = track
+                .into_track(graph)
+                .map_err(|e| format!("animation track {}: {}", i + 1, e))?;
+            timeline.tracks.push(track);
+        }
+        Ok(timeline)
+    }
+}
+
+impl DocTrack {
+    fn into_track(self, graph: &SceneGraph) -> Result<Track, String> {
+        let channel = match (&self.node, self.property, self.camera) {
+            (Some(name), Some(property), None) => {
+                let node = graph.find(name).ok_or(format!("unknown node '{}'", name))?;
+                match property {
+                    NodeProperty::Translation => Channel::NodeTranslation(node),
+                    NodeProperty::Rotation => Channel::NodeRotation(node),
+                    NodeProperty::Scale => Channel::NodeScale(node),
+                }
+            }
+            (Some(_), None, None) => return Err("node tracks need a property".to_string()),
+            (None, None, Some(CameraProperty::Position)) => Channel::CameraPosition,
+            (None, None, Some(CameraProperty::Target)) => Channel::CameraTarget,
+            _ => return Err("needs either a node and a property or a camera".to_string()),
+        };
+
+        if self.keys.is_empty() {
+            return Err("needs at least one key".to_string());
+        }
+        if self
+            .keys
+            .iter()
+            .any(|key| key.time < 0.0 || !key.time.is_finite())
+        {
+            return Err("key times must be finite and not negative".to_string());
+        }
+        let keyframes = self
+            .keys
+            .iter()
+            .map(|key| Keyframe {
+                time: key.time,
+                value: match channel {
+                    Channel::NodeRotation(_) => radians(key.value),
+                    _ => vec3(key.value),
+                },
+            })
+            .collect();
+        Ok(Track::new(
+            channel,
+            keyframes,
+            self.interpolation.unwrap_or(Interpolation::Linear),
+            self.easing.unwrap_or(Easing::Linear),
+        ))
+    }
+}
+
+impl DocTransform {
+    // Overwrite the values given in the file, angles are in degrees
+    fn apply(self, translation: &mut Vec3, rotation: &mut Vec3, scale: &mut Vec3, spin: &mut Vec3) {
+        if let Some(value) = self.translation {
+            *translation = vec3(value);
+        }
+        if let Some(value) = self.rotation {
+            *rotation = radians(value);
+        }
+        if let Some(value) = self.scale {
+            *scale = vec3(value);
+        }
+        if let Some(value) = self.spin {
+            *spin = radians(value);
+        }
+    }
+}
@@ -0,0 +1,537 @@
+// Programmable shading. A vertex shader moves the corners of every face to clip space
+// and outputs varyings, the rasterizer interpolates the varyings over the pixels of the
+// triangle and a fragment shader colors each pixel from them. Uniforms hold the values
+// shared by a whole draw call.
+use sdl2::pixels::Color;
+
+use crate::display::ColorBuffer;
+use std::sync::Arc;
+
+use crate::light::{self, Light};
+use crate::material::Texture;
+use crate::matrix::Matrix;
+use crate::mesh::Mesh;
+use crate::oit::WeightedBuffer;
+use crate::pipeline::{BACK_FACE_COLOR, FRONT_FACE_COLOR};
+use crate::render_state::{PolygonMode, RenderState};
+use crate::shadow::{ShadowSettings, Shadows};
+use crate::vector::{Vec2, Vec3, Vec4};
+
+// Values a vertex shader hands to the fragment shader, blended between the corners of
+// a triangle
+pub trait Varying: Copy {
+    /// Sum of the three values scaled by `weights`, which add up to one
+    fn interpolate(values: &[Self; 3], weights: [f32; 3]) -> Self;
+}
+
+impl Varying for () {
+    fn interpolate(_: &[(); 3], _: [f32; 3]) {}
+}
+
+impl Varying for f32 {
+    fn interpolate(values: &[f32; 3], weights: [f32; 3]) -> f32 {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl Varying for Vec2 {
+    fn interpolate(values: &[Vec2; 3], weights: [f32; 3]) -> Vec2 {
+        Vec2::new(
+            f32::interpolate(&values.map(|v| v.x), weights),
+            f32::interpolate(&values.map(|v| v.y), weights),
+        )
+    }
+}
+
+impl Varying for Vec3 {
+    fn interpolate(values: &[Vec3; 3], weights: [f32; 3]) -> Vec3 {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl Varying for Vec4 {
+    fn interpolate(values: &[Vec4; 3], weights: [f32; 3]) -> Vec4 {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl<A: Varying, B: Varying> Varying for (A, B) {
+    fn interpolate(values: &[(A, B); 3], weights: [f32; 3]) -> (A, B) {
+        (
+            A::interpolate(&values.map(|v| v.0), weights),
+            B::interpolate(&values.map(|v| v.1), weights),
+        )
+    }
+}
+
+impl<A: Varying, B: Varying, C: Varying> Varying for (A, B, C) {
+    fn interpolate(values: &[(A, B, C); 3], weights: [f32; 3]) -> (A, B, C) {
+        (
+            A::interpolate(&values.map(|v| v.0), weights),
+            B::interpolate(&values.map(|v| v.1), weights),
+            C::interpolate(&values.map(|v| v.2), weights),
+        )
+    }
+}
+
+impl<A: Varying, B: Varying, C: Varying, D: Varying> Varying for (A, B, C, D) {
+    fn interpolate(values: &[(A, B, C, D); 3], weights: [f32; 3]) -> (A, B, C, D) {
+        (
+            A::interpolate(&values.map(|v| v.0), weights),
+            B::interpolate(&values.map(|v| v.1), weights),
+            C::interpolate(&values.map(|v| v.2), weights),
+            D::interpolate(&values.map(|v| v.3), weights),
+        )
+    }
+}
+
+// A corner of a face, as the vertex shader sees it
+#[derive(Debug, Clone, Copy)]
+pub struct VertexInput {
+    pub index: usize, // 0-based index of the vertex in the mesh
+    pub position: Vec3,
+    pub normal: Vec3, // vertex normal, or the face normal when the mesh has none
+    pub uv: Vec2,     // zero when the mesh has no texture coordinates
+    pub color: Color, // vertex color, or the face color when the mesh has none
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VertexOutput<V> {
+    pub position: Vec4, // clip space, x and y in [-w, w] and z in [0, w] are visible
+    pub varying: V,
+}
+
+// A pixel covered by a triangle
+#[derive(Debug, Clone, Copy)]
+pub struct Fragment<V> {
+    pub x: u32,
+    pub y: u32,
+    pub depth: f32,         // 0 on the near plane, 1 on the far plane
+    pub front_facing: bool, // the triangle is wound like the front faces
+    pub varying: V,
+}
+
+// Shaders see the uniforms the renderer sets for every instance, and the values of
+// their own program in `program`
+pub trait VertexShader {
+    type Uniforms;
+    type Varying: Varying;
+
+    fn vertex(
+        &self,
+        uniforms: &Uniforms,
+        program: &Self::Uniforms,
+        input: &VertexInput,
+    ) -> VertexOutput<Self::Varying>;
+}
+
+pub trait FragmentShader {
+    type Uniforms;
+    type Varying: Varying;
+
+    /// Color of the pixel, None leaves it untouched
+    fn fragment(
+        &self,
+        uniforms: &Uniforms,
+        program: &Self::Uniforms,
+        fragment: &Fragment<Self::Varying>,
+    ) -> Option<Color>;
+}
+
+// Buffers a draw call renders into
+pub struct Target<'a> {
+    pub color_buffer: &'a mut ColorBuffer,
+    pub depth_buffer: &'a mut [f32],
+    pub transparency: Option<&'a mut WeightedBuffer>, // takes the colors when set
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Target<'_> {
+    /// Blend the fragments collected in the weighted transparency buffer over the
+    /// color buffer, drawing into the color buffer again afterwards
+    pub fn composite_transparency(&mut self) {
+        if let Some(transparency) = self.transparency.take() {
+            transparency.composite(self.color_buffer);
+        }
+    }
+}
+
+// Corner of a triangle in screen space
+#[derive(Clone, Copy)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    depth: f32,
+    inverse_w: f32, // for perspective correct varyings
+}
+
+impl ScreenVertex {
+    /// Screen position of a clip space position, None behind the near plane
+    fn from_clip(position: Vec4, width: u32, height: u32) -> Option<ScreenVertex> {
+        if position.w <= f32::EPSILON || position.z < 0.0 {
+            return None;
+        }
+        let inverse_w = 1.0 / position.w;
+        Some(ScreenVertex {
+            // y is flipped as it grows down on the screen
+            x: (position.x * inverse_w + 1.0) * width as f32 / 2.0,
+            y: (1.0 - position.y * inverse_w) * height as f32 / 2.0,
+            depth: position.z * inverse_w,
+            inverse_w,
+        })
+    }
+}
+
+// Twice the signed area of the triangle a, b, p
+fn edge(a: &ScreenVertex, b: &ScreenVertex, px: f32, py: f32) -> f32 {
+    (b.x - a.x) * (py - a.y) - (b.y - a.y) * (px - a.x)
+}
+
+/// Draw every face of `mesh` with the shaders, culled, filled, depth tested and
+/// blended as `state` says. Faces crossing the near plane are skipped.
+pub fn draw<VS, FS>(
+    target: &mut Target,
+    mesh: &Mesh,
+    state: &RenderState,
+    uniforms: &Uniforms,
+    program: &VS::Uniforms,
+    vertex_shader: &VS,
+    fragment_shader: &FS,
+) where
+    VS: VertexShader,
+    FS: FragmentShader<Uniforms = VS::Uniforms, Varying = VS::Varying>,
+{
+    for face in &mesh.faces {
+        let corners = [face.a - 1, face.b - 1, face.c - 1];
+        let positions = corners.map(|index| mesh.vertices[index]);
+        let face_normal = (positions[1] - positions[0])
+            .cross(positions[2] - positions[0])
+            .normalize();
+
+        let outputs = corners.map(|index| {
+            let input = VertexInput {
+                index,
+                position: mesh.vertices[index],
+                normal: mesh.normals.get(index).copied().unwrap_or(face_normal),
+                uv: mesh.uvs.get(index).copied().unwrap_or(Vec2::new(0.0, 0.0)),
+                color: mesh.vertex_colors.get(index).copied().unwrap_or(face.color),
+            };
+            vertex_shader.vertex(uniforms, program, &input)
+        });
+        let screen = outputs
+            .map(|output| ScreenVertex::from_clip(output.position, target.width, target.height));
+        if let [Some(a), Some(b), Some(c)] = screen {
+            let triangle = Triangle {
+                vertices: [a, b, c],
+                varyings: outputs.map(|output| output.varying),
+            };
+            triangle.rasterize(target, state, uniforms, program, fragment_shader);
+        }
+    }
+}
+
+struct Triangle<V> {
+    vertices: [ScreenVertex; 3],
+    varyings: [V; 3],
+}
+
+impl<V: Varying> Triangle<V> {
+    fn rasterize<FS: FragmentShader<Varying = V>>(
+        &self,
+        target: &mut Target,
+        state: &RenderState,
+        uniforms: &Uniforms,
+        program: &FS::Uniforms,
+        fragment_shader: &FS,
+    ) {
+        let [a, b, c] = &self.vertices;
+        let area = edge(a, b, c.x, c.y);
+        if area.abs() <= f32::EPSILON || state.culls(area) {
+            return;
+        }
+        let (width, height) = (target.width, target.height);
+        let front_facing = state.is_front(area);
+        let mut shade = |x: f32, y: f32, weights: [f32; 3]| {
+            self.shade(
+                target,
+                state,
+                x,
+                y,
+                weights,
+                front_facing,
+                uniforms,
+                program,
+                fragment_shader,
+            )
+        };
+
+        match state.polygon_mode {
+            PolygonMode::Fill => {
+                // pixels whose center is inside the triangle, within the target
+                let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+                let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+                let max_x = (a.x.max(b.x).max(c.x).ceil() as u32).min(width);
+                let max_y = (a.y.max(b.y).max(c.y).ceil() as u32).min(height);
+
+                for y in min_y..max_y {
+                    for x in min_x..max_x {
+                        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                        // barycentric weights, all positive inside whatever the winding
+                        let weights = [edge(b, c, px, py), edge(c, a, px, py), edge(a, b, px, py)]
+                            .map(|weight| weight / area);
+                        if weights.iter().all(|&weight| weight >= 0.0) {
+                            shade(px, py, weights);
+                        }
+                    }
+                }
+            }
+            PolygonMode::Line => {
+                // a pixel for every step along the longest axis of each edge
+                for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+                    let (from, to) = (&self.vertices[i], &self.vertices[j]);
+                    let steps = (to.x - from.x)
+                        .abs()
+                        .max((to.y - from.y).abs())
+                        .ceil()
+                        .max(1.0);
+                    for step in 0..=steps as u32 {
+                        let t = step as f32 / steps;
+                        let mut weights = [0.0; 3];
+                        weights[i] = 1.0 - t;
+                        weights[j] = t;
+                        shade(
+                            from.x + (to.x - from.x) * t,
+                            from.y + (to.y - from.y) * t,
+                            weights,
+                        );
+                    }
+                }
+            }
+            PolygonMode::Point => {
+                for (i, vertex) in self.vertices.iter().enumerate() {
+                    let mut weights = [0.0; 3];
+                    weights[i] = 1.0;
+                    shade(vertex.x, vertex.y, weights);
+                }
+            }
+        }
+    }
+
+    // Depth test the point of the triangle at `weights` and run the fragment shader on
+    // the pixel it falls in
+    #[allow(clippy::too_many_arguments)]
+    fn shade<FS: FragmentShader<Varying = V>>(
+        &self,
+        target: &mut Target,
+        state: &RenderState,
+        x: f32,
+        y: f32,
+        weights: [f32; 3],
+        front_facing: bool,
+        uniforms: &Uniforms,
+        program: &FS::Uniforms,
+        fragment_shader: &FS,
+    ) {
+        if x < 0.0 || y < 0.0 || x >= target.width as f32 || y >= target.height as f32 {
+            return;
+        }
+        let (x, y) = (x as u32, y as u32);
+        let index = (y * target.width + x) as usize;
+        let [a, b, c] = &self.vertices;
+        let depth = weights[0] * a.depth + weights[1] * b.depth + weights[2] * c.depth;
+        if !state.depth_func.passes(depth, target.depth_buffer[index]) {
+            return;
+        }
+
+        // weights in clip space, so varyings follow the perspective
+        let perspective = [0, 1, 2].map(|i| weights[i] * self.vertices[i].inverse_w);
+        let sum: f32 = perspective.iter().sum();
+        let fragment = Fragment {
+            x,
+            y,
+            depth,
+            front_facing,
+            varying: V::interpolate(&self.varyings, perspective.map(|w| w / sum)),
+        };
+        if let Some(color) = fragment_shader.fragment(uniforms, program, &fragment) {
+            if state.depth_write {
+                target.depth_buffer[index] = depth;
+            }
+            if let Some(transparency) = target.transparency.as_deref_mut() {
+                transparency.accumulate(x, y, color, depth);
+            } else if let Some(pixel) = target.color_buffer.pixel_mut(x, y) {
+                state.write_color(pixel, color);
+            }
+        }
+    }
+}
+
+// Values the renderer passes to the shaders of every instance it draws
+pub struct Uniforms {
+    pub model_view: Matrix,
+    pub normal_matrix: Matrix, // inverse transpose of model_view, keeps normals perpendicular
+    pub projection: Matrix,
+    pub lights: Vec<Light>, // in view space
+    pub ambient: f32,
+    pub shadows: Shadows, // of the lights, in the same order
+    pub apply_light: bool,
+    pub facing_colors: bool, // color front and back faces instead of their own color
+    pub base_color: Option<Color>, // material color replacing the vertex colors
+    pub texture: Option<Arc<Texture>>, // multiplies the colors at the uvs
+    pub opacity: f32,        // alpha of the colors, from the material
+}
+
+impl Default for Uniforms {
+    fn default() -> Uniforms {
+        Uniforms::new()
+    }
+}
+
+impl Uniforms {
+    pub fn new() -> Uniforms {
+        Uniforms {
+            model_view: Matrix::identity(),
+            normal_matrix: Matrix::identity(),
+            projection: Matrix::identity(),
+            lights: Vec::new(),
+            ambient: 0.0,
+            shadows: Shadows::new(ShadowSettings::default()),
+            apply_light: true,
+            facing_colors: false,
+            base_color: None,
+            texture: None,
+            opacity: 1.0,
+        }
+    }
+
+    /// Set the model view matrix of the instance drawn next, and the normal matrix
+    /// from it. Matrices without an inverse transform the normals as they are.
+    pub fn set_model_view(&mut self, model_view: Matrix) {
+        self.model_view = model_view;
+        self.normal_matrix = model_view
+            .inverse()
+            .map_or(model_view, |inverse| inverse.transpose());
+    }
+}
+
+// A vertex and fragment shader pair the renderer can draw instances with
+pub trait Program {
+    fn draw(&self, target: &mut Target, mesh: &Mesh, state: &RenderState, uniforms: &Uniforms);
+}
+
+pub struct ShaderProgram<VS: VertexShader, FS> {
+    pub vertex: VS,
+    pub fragment: FS,
+    pub uniforms: VS::Uniforms, // the values of its own the shaders are given
+}
+
+impl<VS, FS> Program for ShaderProgram<VS, FS>
+where
+    VS: VertexShader,
+    FS: FragmentShader<Uniforms = VS::Uniforms, Varying = VS::Varying>,
+{
+    fn draw(&self, target: &mut Target, mesh: &Mesh, state: &RenderState, uniforms: &Uniforms) {
+        draw(
+            target,
+            mesh,
+            state,
+            uniforms,
+            &self.uniforms,
+            &self.vertex,
+            &self.fragment,
+        );
+    }
+}
+
+// Diffuse lighting computed for every pixel from the interpolated normal
+pub struct Lambert;
+
+// View space normal and position, color in [0, 1] and texture coordinates
+type LambertVarying = (Vec3, Vec3, Vec3, Vec2);
+
+impl VertexShader for Lambert {
+    type Uniforms = ();
+    type Varying = LambertVarying;
+
+    fn vertex(
+        &self,
+        uniforms: &Uniforms,
+        _: &(),
+        input: &VertexInput,
+    ) -> VertexOutput<LambertVarying> {
+        let mut model_view = uniforms.model_view;
+        let mut normal_matrix = uniforms.normal_matrix;
+        let mut projection = uniforms.projection;
+        let mut view = model_view.multiply(&mut Vec4::from_vec3(input.position));
+        let mut normal = Vec4::from_vec3(input.normal);
+        normal.w = 0.0;
+        let normal = Vec3::from_vec4(normal_matrix.multiply(&mut normal));
+
+        let position = Vec3::from_vec4(view);
+
+        let color = uniforms.base_color.unwrap_or(input.color);
+        VertexOutput {
+            position: projection.multiply(&mut view),
+            varying: (
+                normal,
+                position,
+                Vec3::new(color.r as f32, color.g as f32, color.b as f32) / 255.0,
+                input.uv,
+            ),
+        }
+    }
+}
+
+impl FragmentShader for Lambert {
+    type Uniforms = ();
+    type Varying = LambertVarying;
+
+    fn fragment(
+        &self,
+        uniforms: &Uniforms,
+        _: &(),
+        fragment: &Fragment<LambertVarying>,
+    ) -> Option<Color> {
+        let (normal, position, mut color, uv) = fragment.varying;
+        if uniforms.facing_colors {
+            let facing = if fragment.front_facing {
+                FRONT_FACE_COLOR
+            } else {
+                BACK_FACE_COLOR
+            };
+            color = Vec3::new(facing.r as f32, facing.g as f32, facing.b as f32) / 255.0;
+        } else if let Some(texture) = &uniforms.texture {
+            let texel = texture.sample(uv);
+            color.x *= texel.r as f32 / 255.0;
+            color.y *= texel.g as f32 / 255.0;
+            color.z *= texel.b as f32 / 255.0;
+        }
+        let mut light = Vec3::new(1.0, 1.0, 1.0);
+        if uniforms.apply_light {
+            light = light::illuminate(
+                &uniforms.lights,
+                uniforms.ambient,
+                &uniforms.shadows,
+                position,
+                normal.normalize(),
+            );
+        }
+        let channel = |value: f32, light: f32| (value * light * 255.0).clamp(0.0, 255.0) as u8;
+        Some(Color::RGBA(
+            channel(color.x, light.x),
+            channel(color.y, light.y),
+            channel(color.z, light.z),
+            (uniforms.opacity * 255.0).clamp(0.0, 255.0) as u8,
+        ))
+    }
+}
+
+/// The program instances without one of their own are drawn with
+pub fn lambert() -> ShaderProgram<Lambert, Lambert> {
+    ShaderProgram {
+        vertex: Lambert,
+        fragment: Lambert,
+        uniforms: (),
+    }
+}
@@ -0,0 +1,258 @@
+// Shadow mapping. Every frame the casters are drawn from each directional and spot
+// light into a depth map, and a point being lit looks itself up in the maps: where
+// something nearer to the light was drawn, it is in shadow. Depths are compared as
+// distances along the light's view axis, so the bias is in world units whatever the
+// projection, and neighbouring texels are averaged (percentage closer filtering) to
+// soften the jagged edges of the map.
+use sdl2::pixels::Color;
+use serde::Deserialize;
+
+use crate::bounds::BoundingSphere;
+use crate::display::{self, ColorBuffer, PixelFormat};
+use crate::light::{Light, LightKind};
+use crate::matrix::Matrix;
+use crate::mesh::Mesh;
+use crate::render_state::{CullMode, RenderState};
+use crate::shader::{
+    self, Fragment, FragmentShader, Target, Uniforms, VertexInput, VertexOutput, VertexShader,
+};
+use crate::vector::{Vec3, Vec4};
+
+// Widest spot light cone a map is drawn for, a perspective can't cover half a sphere
+const MAX_SPOT_FOV: f32 = 170.0;
+
+// Texels on each side of the largest maps, the depths of one take 256 MiB
+pub const MAX_SIZE: u32 = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowSettings {
+    pub size: u32, // texels on each side of the maps, up to MAX_SIZE
+    pub bias: f32, // world units a point is moved toward the light, against acne
+    pub pcf: u32,  // radius in texels of the filter, 0 for hard edges
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings {
+            size: 1024,
+            bias: 0.05,
+            pcf: 1,
+        }
+    }
+}
+
+// Depths seen from one light
+pub struct ShadowMap {
+    depth: Vec<f32>, // distance along the light's view axis, row by row
+    size: u32,
+    view_to_light: Matrix, // from the camera's view space to the light's
+    projection: Matrix,
+}
+
+impl ShadowMap {
+    fn new(size: u32) -> ShadowMap {
+        ShadowMap {
+            depth: vec![1.0; (size * size) as usize],
+            size,
+            view_to_light: Matrix::identity(),
+            projection: Matrix::identity(),
+        }
+    }
+
+    /// Fraction of the light reaching `position`, in the camera's view space
+    fn visibility(&self, position: Vec3, settings: &ShadowSettings) -> f32 {
+        let mut view_to_light = self.view_to_light;
+        let mut projection = self.projection;
+        let mut light_view = view_to_light.multiply(&mut Vec4::from_vec3(position));
+        let depth = light_view.z;
+        let clip = projection.multiply(&mut light_view);
+        if clip.w <= f32::EPSILON {
+            return 1.0;
+        }
+        // texel of the point, the way the rasterizer maps clip space to the screen
+        let size = self.size as f32;
+        let x = ((clip.x / clip.w + 1.0) * size / 2.0).floor() as i64;
+        let y = ((1.0 - clip.y / clip.w) * size / 2.0).floor() as i64;
+
+        let radius = settings.pcf as i64;
+        let mut lit = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (x, y) = (x + dx, y + dy);
+                // outside the map nothing casts a shadow
+                let inside =
+                    (0..self.size as i64).contains(&x) && (0..self.size as i64).contains(&y);
+                if !inside
+                    || depth - settings.bias <= self.depth[(y * self.size as i64 + x) as usize]
+                {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / ((2 * radius + 1) * (2 * radius + 1)) as f32
+    }
+}
+
+// Shadow maps of the lights of a scene
+pub struct Shadows {
+    pub settings: ShadowSettings,
+    maps: Vec<Option<ShadowMap>>, // one for every light, None for point lights
+    empty: ColorBuffer,           // the depth pass writes no colors
+}
+
+impl Shadows {
+    pub fn new(settings: ShadowSettings) -> Shadows {
+        Shadows {
+            settings,
+            maps: Vec::new(),
+            empty: ColorBuffer::new(0, 0, PixelFormat::Rgb24),
+        }
+    }
+
+    /// No shadows until the next `render`
+    pub fn clear(&mut self) {
+        self.maps.clear();
+    }
+
+    /// Draw the depth of `casters`, meshes with their world matrix, from every
+    /// directional and spot light of `lights`. `bounds` holds all the casters and
+    /// `view_matrix` is the camera's
+    pub fn render(
+        &mut self,
+        lights: &[Light],
+        casters: &[(&Mesh, Matrix)],
+        bounds: &BoundingSphere,
+        view_matrix: &Matrix,
+    ) {
+        let Some(view_inverse) = view_matrix.inverse() else {
+            self.clear();
+            return;
+        };
+        let size = self.settings.size.min(MAX_SIZE);
+        self.maps.resize_with(lights.len(), || None);
+        for (map, light) in self.maps.iter_mut().zip(lights) {
+            let Some((light_view, projection, near, far)) = light_matrices(light, bounds) else {
+                *map = None;
+                continue;
+            };
+            let map = match map {
+                Some(map) if map.size == size => map,
+                _ => map.insert(ShadowMap::new(size)),
+            };
+            map.view_to_light = view_inverse.multiply_matrix(&light_view);
+            map.projection = projection;
+
+            display::clear_depth_buffer(&mut map.depth);
+            let mut target = Target {
+                color_buffer: &mut self.empty,
+                depth_buffer: &mut map.depth,
+                transparency: None,
+                width: size,
+                height: size,
+            };
+            // both sides, so open meshes like planes cast shadows too
+            let state = RenderState {
+                cull_mode: CullMode::None,
+                ..Default::default()
+            };
+            let mut uniforms = Uniforms::new();
+            uniforms.projection = projection;
+            for (mesh, world_matrix) in casters {
+                uniforms.model_view = world_matrix.multiply_matrix(&light_view);
+                shader::draw(
+                    &mut target,
+                    mesh,
+                    &state,
+                    &uniforms,
+                    &(),
+                    &DepthOnly,
+                    &DepthOnly,
+                );
+            }
+
+            // depths from 0 to 1 back to distances, empty texels end up at the far plane
+            let orthographic = light.kind == LightKind::Directional;
+            for depth in map.depth.iter_mut() {
+                *depth = if orthographic {
+                    near + *depth * (far - near)
+                } else {
+                    near * far / (far - *depth * (far - near))
+                };
+            }
+        }
+    }
+
+    /// Fraction of light `index` reaching `position`, in the camera's view space
+    pub fn visibility(&self, index: usize, position: Vec3) -> f32 {
+        match self.maps.get(index) {
+            Some(Some(map)) => map.visibility(position, &self.settings),
+            _ => 1.0,
+        }
+    }
+}
+
+// View and projection matrices of the light, with the near and far distances of the
+// projection, covering `bounds`. None for point lights, which cast no shadows
+fn light_matrices(light: &Light, bounds: &BoundingSphere) -> Option<(Matrix, Matrix, f32, f32)> {
+    let direction = light.direction.normalize();
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let radius = bounds.radius.max(f32::EPSILON);
+    match light.kind {
+        LightKind::Point => None,
+        LightKind::Directional => {
+            // a box around the bounds, seen from outside them
+            let eye = bounds.center - direction * (2.0 * radius);
+            let view = Matrix::look_at(eye, bounds.center, up);
+            let (near, far) = (radius, 3.0 * radius);
+            Some((
+                view,
+                Matrix::make_orthographic(radius, radius, near, far),
+                near,
+                far,
+            ))
+        }
+        LightKind::Spot => {
+            let view = Matrix::look_at(light.position, light.position + direction, up);
+            let far = light
+                .range
+                .unwrap_or((bounds.center - light.position).len() + radius);
+            let near = (far * 0.001).max(0.01);
+            let fov = (2.0 * light.outer_cone).min(MAX_SPOT_FOV.to_radians());
+            Some((
+                view,
+                Matrix::make_perspetive(fov, 1.0, near, far),
+                near,
+                far,
+            ))
+        }
+    }
+}
+
+// Moves the casters to the light's clip space and only writes their depth
+struct DepthOnly;
+
+impl VertexShader for DepthOnly {
+    type Uniforms = ();
+    type Varying = ();
+
+    fn vertex(&self, uniforms: &Uniforms, _: &(), input: &VertexInput) -> VertexOutput<()> {
+        let mut model_view = uniforms.model_view;
+        let mut projection = uniforms.projection;
+        let mut view = model_view.multiply(&mut Vec4::from_vec3(input.position));
+        VertexOutput {
+            position: projection.multiply(&mut view),
+            varying: (),
+        }
+    }
+}
+
+impl FragmentShader for DepthOnly {
+    type Uniforms = ();
+    type Varying = ();
+
+    fn fragment(&self, _uniforms: &Uniforms, _: &(), _fragment: &Fragment<()>) -> Option<Color> {
+        // any color, so the depth is written
+        Some(Color::RGB(0, 0, 0))
+    }
+}
@@ -0,0 +1,283 @@
+// SIMD kernels behind Vec4 and Matrix: SSE2 on x86_64 (AVX for batches when the CPU
+// has it), NEON on aarch64 and plain scalar code elsewhere. The kernels multiply and
+// add in the same order as the scalar code, so every path gives the same results
+use crate::vector::Vec4;
+
+/// `v` times `m` with row vectors, the rows of `m` scaled by the components of `v`
+/// and summed
+#[inline]
+pub fn transform(v: Vec4, m: &[[f32; 4]; 4]) -> Vec4 {
+    imp::transform(v, m)
+}
+
+/// `transform` every vector of `vectors` by `m`, in place
+pub fn transform_batch(vectors: &mut [Vec4], m: &[[f32; 4]; 4]) {
+    imp::transform_batch(vectors, m)
+}
+
+#[inline]
+pub fn add(a: Vec4, b: Vec4) -> Vec4 {
+    imp::add(a, b)
+}
+
+#[inline]
+pub fn sub(a: Vec4, b: Vec4) -> Vec4 {
+    imp::sub(a, b)
+}
+
+#[inline]
+pub fn scale(v: Vec4, scalar: f32) -> Vec4 {
+    imp::scale(v, scalar)
+}
+
+// Reference implementation, and the one used on other architectures
+#[cfg(any(test, not(any(target_arch = "x86_64", target_arch = "aarch64"))))]
+pub mod scalar {
+    use crate::vector::Vec4;
+
+    pub fn transform(v: Vec4, m: &[[f32; 4]; 4]) -> Vec4 {
+        Vec4::new(
+            v.x * m[0][0] + v.y * m[1][0] + v.z * m[2][0] + v.w * m[3][0],
+            v.x * m[0][1] + v.y * m[1][1] + v.z * m[2][1] + v.w * m[3][1],
+            v.x * m[0][2] + v.y * m[1][2] + v.z * m[2][2] + v.w * m[3][2],
+            v.x * m[0][3] + v.y * m[1][3] + v.z * m[2][3] + v.w * m[3][3],
+        )
+    }
+
+    pub fn transform_batch(vectors: &mut [Vec4], m: &[[f32; 4]; 4]) {
+        for v in vectors.iter_mut() {
+            *v = transform(*v, m);
+        }
+    }
+
+    pub fn add(a: Vec4, b: Vec4) -> Vec4 {
+        Vec4::new(a.x + b.x, a.y + b.y, a.z + b.z, a.w + b.w)
+    }
+
+    pub fn sub(a: Vec4, b: Vec4) -> Vec4 {
+        Vec4::new(a.x - b.x, a.y - b.y, a.z - b.z, a.w - b.w)
+    }
+
+    pub fn scale(v: Vec4, scalar: f32) -> Vec4 {
+        Vec4::new(v.x * scalar, v.y * scalar, v.z * scalar, v.w * scalar)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use std::arch::x86_64::*;
+
+    use crate::vector::Vec4;
+
+    // Vec4 is four packed f32s
+    #[inline]
+    fn load(v: &Vec4) -> __m128 {
+        unsafe { _mm_load_ps(v as *const Vec4 as *const f32) }
+    }
+
+    #[inline]
+    fn store(v: __m128) -> Vec4 {
+        let mut result = Vec4::new(0.0, 0.0, 0.0, 0.0);
+        unsafe { _mm_store_ps(&mut result as *mut Vec4 as *mut f32, v) };
+        result
+    }
+
+    #[inline]
+    fn rows(m: &[[f32; 4]; 4]) -> [__m128; 4] {
+        unsafe { m.map(|row| _mm_loadu_ps(row.as_ptr())) }
+    }
+
+    #[inline]
+    pub fn transform(v: Vec4, m: &[[f32; 4]; 4]) -> Vec4 {
+        let [r0, r1, r2, r3] = rows(m);
+        let v = load(&v);
+        // Safety: SSE2 is part of x86_64
+        unsafe {
+            let x = _mm_shuffle_ps::<0b00_00_00_00>(v, v);
+            let y = _mm_shuffle_ps::<0b01_01_01_01>(v, v);
+            let z = _mm_shuffle_ps::<0b10_10_10_10>(v, v);
+            let w = _mm_shuffle_ps::<0b11_11_11_11>(v, v);
+            let sum = _mm_add_ps(_mm_mul_ps(x, r0), _mm_mul_ps(y, r1));
+            let sum = _mm_add_ps(sum, _mm_mul_ps(z, r2));
+            store(_mm_add_ps(sum, _mm_mul_ps(w, r3)))
+        }
+    }
+
+    pub fn transform_batch(vectors: &mut [Vec4], m: &[[f32; 4]; 4]) {
+        if is_x86_feature_detected!("avx") {
+            // Safety: the CPU supports AVX
+            unsafe { transform_batch_avx(vectors, m) }
+        } else {
+            for v in vectors.iter_mut() {
+                *v = transform(*v, m);
+            }
+        }
+    }
+
+    // Two vectors at a time, one in each 128 bit lane
+    #[target_feature(enable = "avx")]
+    unsafe fn transform_batch_avx(vectors: &mut [Vec4], m: &[[f32; 4]; 4]) {
+        let [r0, r1, r2, r3] = rows(m).map(|row| _mm256_set_m128(row, row));
+        let mut pairs = vectors.chunks_exact_mut(2);
+        for pair in &mut pairs {
+            let pointer = pair.as_mut_ptr() as *mut f32;
+            let v = unsafe { _mm256_loadu_ps(pointer) };
+            let x = _mm256_permute_ps::<0b00_00_00_00>(v);
+            let y = _mm256_permute_ps::<0b01_01_01_01>(v);
+            let z = _mm256_permute_ps::<0b10_10_10_10>(v);
+            let w = _mm256_permute_ps::<0b11_11_11_11>(v);
+            let sum = _mm256_add_ps(_mm256_mul_ps(x, r0), _mm256_mul_ps(y, r1));
+            let sum = _mm256_add_ps(sum, _mm256_mul_ps(z, r2));
+            let sum = _mm256_add_ps(sum, _mm256_mul_ps(w, r3));
+            unsafe { _mm256_storeu_ps(pointer, sum) };
+        }
+        for v in pairs.into_remainder() {
+            *v = transform(*v, m);
+        }
+    }
+
+    #[inline]
+    pub fn add(a: Vec4, b: Vec4) -> Vec4 {
+        unsafe { store(_mm_add_ps(load(&a), load(&b))) }
+    }
+
+    #[inline]
+    pub fn sub(a: Vec4, b: Vec4) -> Vec4 {
+        unsafe { store(_mm_sub_ps(load(&a), load(&b))) }
+    }
+
+    #[inline]
+    pub fn scale(v: Vec4, scalar: f32) -> Vec4 {
+        unsafe { store(_mm_mul_ps(load(&v), _mm_set1_ps(scalar))) }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use std::arch::aarch64::*;
+
+    use crate::vector::Vec4;
+
+    // Vec4 is four packed f32s
+    #[inline]
+    fn load(v: &Vec4) -> float32x4_t {
+        unsafe { vld1q_f32(v as *const Vec4 as *const f32) }
+    }
+
+    #[inline]
+    fn store(v: float32x4_t) -> Vec4 {
+        let mut result = Vec4::new(0.0, 0.0, 0.0, 0.0);
+        unsafe { vst1q_f32(&mut result as *mut Vec4 as *mut f32, v) };
+        result
+    }
+
+    #[inline]
+    pub fn transform(v: Vec4, m: &[[f32; 4]; 4]) -> Vec4 {
+        // Safety: NEON is part of aarch64
+        unsafe {
+            let [r0, r1, r2, r3] = m.map(|row| vld1q_f32(row.as_ptr()));
+            // separate multiplies and adds, a fused multiply-add rounds differently
+            let sum = vaddq_f32(vmulq_n_f32(r0, v.x), vmulq_n_f32(r1, v.y));
+            let sum = vaddq_f32(sum, vmulq_n_f32(r2, v.z));
+            store(vaddq_f32(sum, vmulq_n_f32(r3, v.w)))
+        }
+    }
+
+    pub fn transform_batch(vectors: &mut [Vec4], m: &[[f32; 4]; 4]) {
+        for v in vectors.iter_mut() {
+            *v = transform(*v, m);
+        }
+    }
+
+    #[inline]
+    pub fn add(a: Vec4, b: Vec4) -> Vec4 {
+        unsafe { store(vaddq_f32(load(&a), load(&b))) }
+    }
+
+    #[inline]
+    pub fn sub(a: Vec4, b: Vec4) -> Vec4 {
+        unsafe { store(vsubq_f32(load(&a), load(&b))) }
+    }
+
+    #[inline]
+    pub fn scale(v: Vec4, scalar: f32) -> Vec4 {
+        unsafe { store(vmulq_n_f32(load(&v), scalar)) }
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+use scalar as imp;
+
+#[cfg(test)]
+mod tests {
+    use super::{imp, scalar};
+    use crate::matrix::Matrix;
+    use crate::vector::{Vec3, Vec4};
+
+    fn components(v: Vec4) -> [f32; 4] {
+        [v.x, v.y, v.z, v.w]
+    }
+
+    // Vectors with fractions, negative values and different magnitudes
+    fn vectors(count: usize) -> Vec<Vec4> {
+        (0..count)
+            .map(|i| {
+                let i = i as f32;
+                Vec4::new(i * 0.37 - 3.1, 1.0 / (i + 1.0), -i * 2.9, i * 0.013 + 1.0)
+            })
+            .collect()
+    }
+
+    fn matrix() -> [[f32; 4]; 4] {
+        [
+            [0.8, -0.1, 0.33, 0.0],
+            [0.25, 1.7, -0.6, 0.01],
+            [-0.45, 0.2, 0.9, 1.0],
+            [3.5, -7.25, 12.1, 0.5],
+        ]
+    }
+
+    #[test]
+    fn kernels_match_scalar() {
+        let m = matrix();
+        let vectors = vectors(16);
+        for (&a, &b) in vectors.iter().zip(vectors.iter().rev()) {
+            let transformed = components(imp::transform(a, &m));
+            assert_eq!(transformed, components(scalar::transform(a, &m)));
+            assert_eq!(components(imp::add(a, b)), components(scalar::add(a, b)));
+            assert_eq!(components(imp::sub(a, b)), components(scalar::sub(a, b)));
+            assert_eq!(
+                components(imp::scale(a, -1.3)),
+                components(scalar::scale(a, -1.3))
+            );
+        }
+    }
+
+    #[test]
+    fn transform_batch_matches_scalar() {
+        let m = matrix();
+        // odd lengths leave a vector over after the pairs of the AVX loop
+        for count in [0, 1, 3, 257] {
+            let mut batch = vectors(count);
+            let mut expected = batch.clone();
+            super::transform_batch(&mut batch, &m);
+            scalar::transform_batch(&mut expected, &m);
+            let batch: Vec<_> = batch.into_iter().map(components).collect();
+            let expected: Vec<_> = expected.into_iter().map(components).collect();
+            assert_eq!(batch, expected, "{} vectors", count);
+        }
+    }
+
+    #[test]
+    fn multiply_matrix_matches_triple_loop() {
+        let a = Matrix { data: matrix() };
+        let b = Matrix::world(
+            Vec3::new(1.5, 0.5, 2.0),
+            Vec3::new(0.3, -1.2, 0.7),
+            Vec3::new(-4.0, 2.5, 9.0),
+        );
+        let expected: [[f32; 4]; 4] = [0, 1, 2, 3]
+            .map(|i| [0, 1, 2, 3].map(|j| (0..4).map(|k| a.data[i][k] * b.data[k][j]).sum()));
+        assert_eq!(a.multiply_matrix(&b).data, expected);
+    }
+}
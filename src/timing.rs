@@ -0,0 +1,53 @@
+// Frame timing for a fixed-timestep update loop
+use std::time::{Duration, Instant};
+
+// Seconds simulated by every update
+pub const FIXED_TIMESTEP: f32 = 1.0 / 120.0;
+
+// Longer frames (a stall, a dragged window) are cut to this, so the updates slow down
+// for a moment instead of piling up
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+pub struct FrameTimer {
+    last_frame: Instant,
+    accumulator: Duration,        // elapsed time not simulated yet
+    frame_time: Option<Duration>, // shortest time between frames, None when uncapped
+}
+
+impl FrameTimer {
+    /// Timer limiting the loop to `max_fps` frames per second, or uncapped with None
+    pub fn new(max_fps: Option<u32>) -> FrameTimer {
+        FrameTimer {
+            last_frame: Instant::now(),
+            accumulator: Duration::ZERO,
+            frame_time: max_fps.map(|fps| Duration::from_secs(1) / fps.max(1)),
+        }
+    }
+
+    /// Start a frame, returning how many fixed steps of `FIXED_TIMESTEP` the updates
+    /// need to catch up with the time elapsed since the previous frame
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = (now - self.last_frame).min(MAX_FRAME_TIME);
+        self.last_frame = now;
+
+        self.accumulator += elapsed;
+        let step = Duration::from_secs_f32(FIXED_TIMESTEP);
+        let mut steps = 0;
+        while self.accumulator >= step {
+            self.accumulator -= step;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Sleep for what is left of the frame when the frame rate is capped
+    pub fn limit(&self) {
+        if let Some(frame_time) = self.frame_time {
+            let elapsed = self.last_frame.elapsed();
+            if elapsed < frame_time {
+                std::thread::sleep(frame_time - elapsed);
+            }
+        }
+    }
+}
@@ -1,27 +1,36 @@
-use crate::{display::draw_line, vector::Vec2};
+use crate::display::{draw_span, ColorBuffer};
+use crate::render_state::BlendMode;
+use crate::vector::Vec2;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Triangle {
-    pub(crate) points: [Vec2; 3],
-    pub(crate) color: sdl2::pixels::Color,
-    pub(crate) avg_depth: f32,
+    pub points: [Vec2; 3],
+    pub color: sdl2::pixels::Color,
+    pub avg_depth: f32,
+    pub blend: BlendMode, // with the alpha of the color
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Face {
-    pub(crate) a: usize,
-    pub(crate) b: usize,
-    pub(crate) c: usize,
-    pub(crate) color: sdl2::pixels::Color,
+    pub a: usize,
+    pub b: usize,
+    pub c: usize,
+    pub color: sdl2::pixels::Color,
 }
 
 #[allow(dead_code)]
 impl Triangle {
-    pub fn new(points: [Vec2; 3], color: sdl2::pixels::Color, avg_depth: f32) -> Triangle {
+    pub fn new(
+        points: [Vec2; 3],
+        color: sdl2::pixels::Color,
+        avg_depth: f32,
+        blend: BlendMode,
+    ) -> Triangle {
         Triangle {
             points,
             color,
             avg_depth,
+            blend,
         }
     }
 }
@@ -33,10 +42,12 @@ impl Face {
     }
 }
 
+/// Fill a triangle, blending `color` into the color buffer with its alpha
 pub fn draw_filled_triangle(
-    color_buffer: &mut Vec<u8>,
+    color_buffer: &mut ColorBuffer,
     points: [Vec2; 3],
     color: sdl2::pixels::Color,
+    blend: BlendMode,
 ) {
     let mut points = points;
 
@@ -49,27 +60,36 @@ pub fn draw_filled_triangle(
     let x2 = points[2].x as i32;
     let y2 = points[2].y as i32;
 
+    let mut span = |x_start: i32, x_end: i32, y: i32| {
+        draw_span(color_buffer, x_start, x_end, y, color, blend);
+    };
     if y1 == y2 {
-        fill_flat_bottom_triangle(color_buffer, x0, y0, x1, y1, x2, y2, color);
+        fill_flat_bottom_triangle(x0, y0, x1, y1, x2, y2, &mut span);
     } else if y0 == y1 {
-        fill_flat_top_triangle(color_buffer, x0, y0, x1, y1, x2, y2, color);
+        fill_flat_top_triangle(x0, y0, x1, y1, x2, y2, &mut span);
     } else {
         let mx = (((x2 - x0) * (y1 - y0)) / (y2 - y0)) + x0; // find the middle point
         let my = y1;
-        fill_flat_bottom_triangle(color_buffer, x0, y0, x1, y1, mx, my, color);
-        fill_flat_top_triangle(color_buffer, x1, y1, mx, my, x2, y2, color);
+        fill_flat_bottom_triangle(x0, y0, x1, y1, mx, my, &mut span);
+        // the middle row is already filled, blending it twice would show a seam
+        fill_flat_top_triangle(x1, y1, mx, my, x2, y2, &mut |x_start, x_end, y| {
+            if y != my {
+                span(x_start, x_end, y);
+            }
+        });
     }
 }
 
+/// Call `draw_span` with the first and last x of every row of a triangle with a flat
+/// top edge, from the bottom up
 pub fn fill_flat_top_triangle(
-    color_buffer: &mut Vec<u8>,
     x0: i32,
     y0: i32,
     x1: i32,
     y1: i32,
     x2: i32,
     y2: i32,
-    color: sdl2::pixels::Color,
+    draw_span: &mut impl FnMut(i32, i32, i32),
 ) {
     // Find the two slopes (two triangle legs)
     let inv_slope_1 = (x2 - x0) as f32 / (y2 - y0) as f32;
@@ -81,21 +101,22 @@ pub fn fill_flat_top_triangle(
 
     // Loop all the scanlines from bottom to top
     for y in (y0..=y2).rev() {
-        draw_line(color_buffer, x_start as i32, y, x_end as i32, y, color);
+        draw_span(x_start as i32, x_end as i32, y);
         x_start -= inv_slope_1;
         x_end -= inv_slope_2;
     }
 }
 
+/// Call `draw_span` with the first and last x of every row of a triangle with a flat
+/// bottom edge, from the top down
 pub fn fill_flat_bottom_triangle(
-    color_buffer: &mut Vec<u8>,
     x0: i32,
     y0: i32,
     x1: i32,
     y1: i32,
     x2: i32,
     y2: i32,
-    color: sdl2::pixels::Color,
+    draw_span: &mut impl FnMut(i32, i32, i32),
 ) {
     // Find the two slopes (two triangle legs)
     let inv_slope_1 = (x1 - x0) as f32 / (y1 - y0) as f32;
@@ -107,7 +128,7 @@ pub fn fill_flat_bottom_triangle(
 
     // Loop all the scanlines from top to bottom
     for y in y0..=y2 {
-        draw_line(color_buffer, x_start as i32, y, x_end as i32, y, color);
+        draw_span(x_start as i32, x_end as i32, y);
         x_start += inv_slope_1;
         x_end += inv_slope_2;
     }
@@ -0,0 +1,404 @@
+// Mesh validation report and repair operations
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::mesh::Mesh;
+use crate::vector::Vec3;
+
+/// Distance under which two vertices are considered the same point
+pub const WELD_EPSILON: f32 = 1e-5;
+
+// Face indices are positions in `Mesh::faces`, vertex indices are 1-based like in `Face`
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub out_of_range_faces: Vec<usize>,
+    pub degenerate_faces: Vec<usize>,
+    // (vertex, earlier vertex at the same position)
+    pub duplicate_vertices: Vec<(usize, usize)>,
+    pub unreferenced_vertices: Vec<usize>,
+    // edges used by more than two faces
+    pub non_manifold_edges: Vec<(usize, usize)>,
+    // edges whose two faces walk them in the same direction
+    pub inconsistent_winding_edges: Vec<(usize, usize)>,
+    // edges used by a single face
+    pub boundary_edges: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Default)]
+pub struct RepairSummary {
+    pub removed_out_of_range_faces: usize,
+    pub welded_vertices: usize,
+    pub removed_degenerate_faces: usize,
+    pub removed_unreferenced_vertices: usize,
+    pub flipped_faces: usize,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.out_of_range_faces.is_empty()
+            && self.degenerate_faces.is_empty()
+            && self.duplicate_vertices.is_empty()
+            && self.unreferenced_vertices.is_empty()
+            && self.non_manifold_edges.is_empty()
+            && self.inconsistent_winding_edges.is_empty()
+            && self.boundary_edges.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn line<T: fmt::Debug>(f: &mut fmt::Formatter, label: &str, items: &[T]) -> fmt::Result {
+            write!(f, "{:<28}{}", label, items.len())?;
+            if !items.is_empty() {
+                let examples: Vec<String> = items
+                    .iter()
+                    .take(5)
+                    .map(|item| format!("{:?}", item))
+                    .collect();
+                write!(f, "  e.g. {}", examples.join(", "))?;
+            }
+            writeln!(f)
+        }
+        line(f, "out of range faces", &self.out_of_range_faces)?;
+        line(f, "degenerate faces", &self.degenerate_faces)?;
+        line(f, "duplicate vertices", &self.duplicate_vertices)?;
+        line(f, "unreferenced vertices", &self.unreferenced_vertices)?;
+        line(f, "non-manifold edges", &self.non_manifold_edges)?;
+        line(
+            f,
+            "inconsistent winding edges",
+            &self.inconsistent_winding_edges,
+        )?;
+        line(f, "open boundary edges", &self.boundary_edges)
+    }
+}
+
+impl fmt::Display for RepairSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "removed {} out of range faces",
+            self.removed_out_of_range_faces
+        )?;
+        writeln!(f, "welded {} vertices", self.welded_vertices)?;
+        writeln!(
+            f,
+            "removed {} degenerate faces",
+            self.removed_degenerate_faces
+        )?;
+        writeln!(
+            f,
+            "removed {} unreferenced vertices",
+            self.removed_unreferenced_vertices
+        )?;
+        writeln!(f, "flipped {} faces", self.flipped_faces)
+    }
+}
+
+impl Mesh {
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let mut referenced = vec![false; self.vertices.len()];
+
+        for (f, face) in self.faces.iter().enumerate() {
+            if !self.face_in_range(f) {
+                report.out_of_range_faces.push(f);
+                continue;
+            }
+            for index in [face.a, face.b, face.c] {
+                referenced[index - 1] = true;
+            }
+            if self.is_degenerate(f) {
+                report.degenerate_faces.push(f);
+            }
+        }
+
+        for (vertex, representative) in self.weld_map(WELD_EPSILON).into_iter().enumerate() {
+            if representative != vertex {
+                report
+                    .duplicate_vertices
+                    .push((vertex + 1, representative + 1));
+            }
+        }
+        report.unreferenced_vertices = (0..self.vertices.len())
+            .filter(|&vertex| !referenced[vertex])
+            .map(|vertex| vertex + 1)
+            .collect();
+
+        let mut edges: Vec<_> = self.edge_faces().into_iter().collect();
+        edges.sort_by_key(|(edge, _)| *edge);
+        for (edge, faces) in edges {
+            match faces.len() {
+                1 => report.boundary_edges.push(edge),
+                2 if faces[0].1 == faces[1].1 => report.inconsistent_winding_edges.push(edge),
+                2 => {}
+                _ => report.non_manifold_edges.push(edge),
+            }
+        }
+        report
+    }
+
+    /// Run every repair in a sensible order
+    pub fn repair(&mut self, epsilon: f32) -> RepairSummary {
+        let face_count = self.faces.len();
+        self.faces = (0..face_count)
+            .filter(|&f| self.face_in_range(f))
+            .map(|f| self.faces[f])
+            .collect();
+
+        RepairSummary {
+            removed_out_of_range_faces: face_count - self.faces.len(),
+            welded_vertices: self.weld_vertices(epsilon),
+            removed_degenerate_faces: self.remove_degenerate_faces(),
+            removed_unreferenced_vertices: self.remove_unreferenced_vertices(),
+            flipped_faces: self.unify_winding(),
+        }
+    }
+
+    /// Merge vertices closer than `epsilon`, the first one keeps its attributes.
+    /// Returns how many vertices were merged away.
+    pub fn weld_vertices(&mut self, epsilon: f32) -> usize {
+        let weld_map = self.weld_map(epsilon);
+        for face in self.faces.iter_mut() {
+            for index in [&mut face.a, &mut face.b, &mut face.c] {
+                if let Some(&representative) = weld_map.get(index.wrapping_sub(1)) {
+                    *index = representative + 1;
+                }
+            }
+        }
+        let keep: Vec<bool> = weld_map
+            .iter()
+            .enumerate()
+            .map(|(vertex, &representative)| vertex == representative)
+            .collect();
+        self.compact_vertices(&keep)
+    }
+
+    /// Drop faces with a repeated vertex or no area, returns how many were removed
+    pub fn remove_degenerate_faces(&mut self) -> usize {
+        let face_count = self.faces.len();
+        self.faces = (0..face_count)
+            .filter(|&f| !self.face_in_range(f) || !self.is_degenerate(f))
+            .map(|f| self.faces[f])
+            .collect();
+        face_count - self.faces.len()
+    }
+
+    /// Drop vertices no face uses, returns how many were removed
+    pub fn remove_unreferenced_vertices(&mut self) -> usize {
+        let mut referenced = vec![false; self.vertices.len()];
+        for face in &self.faces {
+            for index in [face.a, face.b, face.c] {
+                if let Some(used) = referenced.get_mut(index.wrapping_sub(1)) {
+                    *used = true;
+                }
+            }
+        }
+        self.compact_vertices(&referenced)
+    }
+
+    // Removes the vertices not marked to keep, faces must not use them anymore
+    fn compact_vertices(&mut self, keep: &[bool]) -> usize {
+        let mut new_index: Vec<usize> = Vec::with_capacity(keep.len());
+        let mut kept = 0;
+        for &keep in keep {
+            new_index.push(kept);
+            if keep {
+                kept += 1;
+            }
+        }
+
+        let removed = self.vertices.len() - kept;
+        if removed == 0 {
+            return 0;
+        }
+        retain_marked(&mut self.vertices, keep);
+        if !self.normals.is_empty() {
+            retain_marked(&mut self.normals, keep);
+        }
+        if !self.uvs.is_empty() {
+            retain_marked(&mut self.uvs, keep);
+        }
+        if !self.vertex_colors.is_empty() {
+            retain_marked(&mut self.vertex_colors, keep);
+        }
+        for face in self.faces.iter_mut() {
+            for index in [&mut face.a, &mut face.b, &mut face.c] {
+                if let Some(&new) = new_index.get(index.wrapping_sub(1)) {
+                    *index = new + 1;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Flood fill across shared edges so neighbouring faces agree on their winding,
+    /// then turn closed pieces so their faces point outwards. Returns how many faces were flipped.
+    pub fn unify_winding(&mut self) -> usize {
+        let edge_faces = self.edge_faces();
+        let mut flipped = vec![false; self.faces.len()];
+        let mut visited = vec![false; self.faces.len()];
+
+        for start in 0..self.faces.len() {
+            if visited[start] || !self.face_in_range(start) {
+                continue;
+            }
+            visited[start] = true;
+            let mut component = vec![start];
+            let mut closed = true;
+            let mut queue = VecDeque::from([start]);
+
+            while let Some(f) = queue.pop_front() {
+                for (edge, forward) in self.face_edges(f) {
+                    // the direction this face walks the edge once its flip is applied
+                    let forward = forward != flipped[f];
+                    let neighbours = match edge_faces.get(&edge) {
+                        Some(neighbours) => neighbours,
+                        None => continue,
+                    };
+                    if neighbours.len() == 1 {
+                        closed = false;
+                    }
+                    // non-manifold edges don't say which side is right
+                    if neighbours.len() != 2 {
+                        continue;
+                    }
+                    for &(g, g_forward) in neighbours {
+                        if g == f || visited[g] {
+                            continue;
+                        }
+                        visited[g] = true;
+                        // neighbours must walk the shared edge the other way
+                        flipped[g] = g_forward == forward;
+                        component.push(g);
+                        queue.push_back(g);
+                    }
+                }
+            }
+
+            if closed {
+                // outward faces enclose a positive volume
+                let volume: f32 = component
+                    .iter()
+                    .map(|&f| {
+                        let face = self.faces[f];
+                        let (a, b, c) = (
+                            self.vertices[face.a - 1],
+                            self.vertices[face.b - 1],
+                            self.vertices[face.c - 1],
+                        );
+                        let volume = a.dot(b.cross(c));
+                        if flipped[f] {
+                            -volume
+                        } else {
+                            volume
+                        }
+                    })
+                    .sum();
+                if volume < 0.0 {
+                    for &f in &component {
+                        flipped[f] = !flipped[f];
+                    }
+                }
+            }
+        }
+
+        let mut count = 0;
+        for (face, flip) in self.faces.iter_mut().zip(flipped) {
+            if flip {
+                std::mem::swap(&mut face.b, &mut face.c);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn face_in_range(&self, f: usize) -> bool {
+        let face = &self.faces[f];
+        [face.a, face.b, face.c]
+            .iter()
+            .all(|&index| index >= 1 && index <= self.vertices.len())
+    }
+
+    fn is_degenerate(&self, f: usize) -> bool {
+        let face = &self.faces[f];
+        if face.a == face.b || face.b == face.c || face.a == face.c {
+            return true;
+        }
+        let (a, b, c) = (
+            self.vertices[face.a - 1],
+            self.vertices[face.b - 1],
+            self.vertices[face.c - 1],
+        );
+        (b - a).cross(c - a).len() <= f32::EPSILON
+    }
+
+    // (sorted edge, whether the face walks it from the lower to the higher index)
+    fn face_edges(&self, f: usize) -> [((usize, usize), bool); 3] {
+        let face = &self.faces[f];
+        [(face.a, face.b), (face.b, face.c), (face.c, face.a)].map(|(from, to)| {
+            if from < to {
+                ((from, to), true)
+            } else {
+                ((to, from), false)
+            }
+        })
+    }
+
+    fn edge_faces(&self) -> HashMap<(usize, usize), Vec<(usize, bool)>> {
+        let mut edges: HashMap<(usize, usize), Vec<(usize, bool)>> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            // faces with a repeated vertex have no real edges
+            if !self.face_in_range(f) || face.a == face.b || face.b == face.c || face.a == face.c {
+                continue;
+            }
+            for (edge, forward) in self.face_edges(f) {
+                edges.entry(edge).or_default().push((f, forward));
+            }
+        }
+        edges
+    }
+
+    // For every vertex the first vertex within epsilon of it, itself if none
+    fn weld_map(&self, epsilon: f32) -> Vec<usize> {
+        let epsilon = epsilon.max(f32::MIN_POSITIVE);
+        let cell = |v: Vec3| {
+            (
+                (v.x / epsilon).floor() as i64,
+                (v.y / epsilon).floor() as i64,
+                (v.z / epsilon).floor() as i64,
+            )
+        };
+
+        // points within epsilon are at most one grid cell apart
+        let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        let mut weld_map = Vec::with_capacity(self.vertices.len());
+        for (vertex, &position) in self.vertices.iter().enumerate() {
+            let (x, y, z) = cell(position);
+            let mut representative = vertex;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for &other in grid.get(&(x + dx, y + dy, z + dz)).into_iter().flatten() {
+                            if (self.vertices[other] - position).len() <= epsilon {
+                                representative = weld_map[other];
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            grid.entry((x, y, z)).or_default().push(vertex);
+            weld_map.push(representative);
+        }
+        weld_map
+    }
+}
+
+fn retain_marked<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut i = 0;
+    values.retain(|_| {
+        i += 1;
+        keep[i - 1]
+    });
+}
@@ -4,10 +4,12 @@ use std::ops::Mul;
 use std::ops::Neg;
 use std::ops::Sub;
 
-#[derive(Debug, Copy, Clone)]
+use crate::simd;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec2 {
-    pub(crate) x: f32,
-    pub(crate) y: f32,
+    pub x: f32,
+    pub y: f32,
 }
 
 #[allow(dead_code)]
@@ -201,7 +203,9 @@ impl Neg for Vec3 {
 }
 
 
+// Laid out as four packed f32s, so the SIMD code can load it directly
 #[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
 pub struct Vec4 {
     pub x: f32,
     pub y: f32,
@@ -286,12 +290,7 @@ impl Sub for Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: Vec4) -> Vec4 {
-        Vec4 {
-            x: self.x - other.x,
-            y: self.y - other.y,
-            z: self.z - other.z,
-            w: self.w - other.w,
-        }
+        simd::sub(self, other)
     }
 }
 
@@ -299,12 +298,7 @@ impl Add for Vec4 {
     type Output = Vec4;
 
     fn add(self, other: Vec4) -> Vec4 {
-        Vec4 {
-            x: self.x + other.x,
-            y: self.y + other.y,
-            z: self.z + other.z,
-            w: self.w + other.w,
-        }
+        simd::add(self, other)
     }
 }
 
@@ -312,12 +306,7 @@ impl Mul<f32> for Vec4 {
     type Output = Vec4;
 
     fn mul(self, scalar: f32) -> Vec4 {
-        Vec4 {
-            x: self.x * scalar,
-            y: self.y * scalar,
-            z: self.z * scalar,
-            w: self.w * scalar,
-        }
+        simd::scale(self, scalar)
     }
 }
 
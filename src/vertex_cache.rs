@@ -0,0 +1,55 @@
+// Vertices of a mesh transformed once per frame, shared by every face using them
+use rayon::prelude::*;
+
+use crate::pipeline::{VertexStage, VERTEX_BATCH};
+use crate::vector::{Vec2, Vec3, Vec4};
+
+#[derive(Debug, Copy, Clone)]
+pub struct TransformedVertex {
+    pub view: Vec4,   // position in view space
+    pub screen: Vec2, // projected position in pixels
+}
+
+// Vertices are only transformed when a face asks for them, so the vertices of culled
+// chunks are skipped
+#[derive(Default)]
+pub struct VertexCache {
+    vertices: Vec<Option<TransformedVertex>>,
+}
+
+impl VertexCache {
+    pub fn new() -> VertexCache {
+        VertexCache::default()
+    }
+
+    /// Forget the cached vertices and make room for a mesh of `count` vertices,
+    /// keeping the memory of the previous mesh
+    pub fn reset(&mut self, count: usize) {
+        self.vertices.clear();
+        self.vertices.resize(count, None);
+    }
+
+    /// Vertex `index`, computed with `transform` the first time it is used
+    pub fn get_or_insert_with(
+        &mut self,
+        index: usize,
+        transform: impl FnOnce() -> TransformedVertex,
+    ) -> TransformedVertex {
+        *self.vertices[index].get_or_insert_with(transform)
+    }
+
+    /// Transform all the `vertices` at once, in batches spread over the thread pool
+    pub fn transform_all(&mut self, vertices: &[Vec3], stage: &VertexStage) {
+        self.vertices.clear();
+        self.vertices.resize(vertices.len(), None);
+        self.vertices
+            .par_chunks_mut(VERTEX_BATCH)
+            .zip(vertices.par_chunks(VERTEX_BATCH))
+            .for_each(|(out, vertices)| stage.transform_batch(vertices, out));
+    }
+
+    /// Vertex `index`, which must have been transformed already
+    pub fn get(&self, index: usize) -> TransformedVertex {
+        self.vertices[index].expect("vertex not transformed")
+    }
+}